@@ -1,7 +1,10 @@
 use crate::error::Result;
-use crate::memory::Layout;
+use crate::il;
+use crate::memory::{Layout, SectionFlags};
+use crate::symbol::Symbols;
 use smallvec::SmallVec;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
 
@@ -9,19 +12,217 @@ pub mod sh2e;
 
 
 
-pub struct Disassembly {
+/// Returns the architecture with the given name, or None if it is not supported
+pub fn from_name(name: &str) -> Option<Box<dyn Architecture>> {
+	match name.to_lowercase().as_str() {
+		"sh2e" | "sh2" | "sh" => Some(Box::new(sh2e::SH2E::new())),
+		_ => None,
+	}
+}
+
 
+
+/// Instructions found by following control flow from the entry points.
+/// Memory that is not covered by an instruction is treated as data.
+#[derive(Default)]
+pub struct Disassembly {
+	instructions: BTreeMap<usize, (Instruction, usize)>, // Instruction and size by address
+	entry_points: BTreeSet<usize>,
+	invalid: BTreeSet<usize>,	// Reached addresses that could not be decoded
+	calls: BTreeSet<usize>,		// Targets of calls
+	resolved: BTreeMap<usize, usize>, // Targets of register branches that were found by the traversal
+	literals: BTreeMap<usize, usize>, // Size of the literals loaded by decoded instructions by address
+	worklist: Vec<usize>,		// Addresses waiting to be traversed
 }
 
 
 #[derive(Debug)]
 pub struct Instruction {
 	tokens: SmallVec<[Token; 6]>,
+	operands: SmallVec<[Operand; 3]>,
+	literal: Option<Literal>,
+	info: InstructionInfo,
 }
 
 
-impl fmt::Display for Instruction {
+
+/// Destination of a control flow transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchTarget {
+	Direct(usize),
+	Register(RegisterId),	// Address held in the register
+	Indirect,				// Otherwise computed at runtime (e.g. PC + register)
+}
+
+
+
+/// Kind of control flow transfer performed by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchKind {
+	None,							// Continues to the next instruction
+	Jump(BranchTarget),
+	ConditionalJump(BranchTarget),	// Continues after the delay slots if not taken
+	Call(BranchTarget),				// Returns after the delay slots
+	Return,
+	Trap,							// Software exception; returns to the next instruction
+}
+
+
+
+/// Control flow metadata of a decoded instruction
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionInfo {
+	pub branch: BranchKind,
+	/// Amount of instructions following this one that execute before the branch takes effect
+	pub delay_slots: usize,
+	/// The instruction raises an exception when placed in a delay slot
+	pub illegal_in_delay_slot: bool,
+}
+
+
+
+/// Entry of an exception vector table
+#[derive(Debug, Clone)]
+pub struct Vector {
+	/// Address of the entry in the table
+	pub address: usize,
+	pub name: String,
+	/// Value stored in the entry
+	pub value: usize,
+	/// The value is the address of a handler rather than data (e.g. an initial stack pointer)
+	pub handler: bool,
+}
+
+
+
+impl InstructionInfo {
+	/// Returns the destination of the branch, if the instruction transfers control elsewhere
+	pub fn target(&self) -> Option<BranchTarget> {
+		match self.branch {
+			BranchKind::Jump(target) | BranchKind::ConditionalJump(target) | BranchKind::Call(target) => Some(target),
+			_ => None,
+		}
+	}
+
+	/// Returns true if execution can continue after the instruction and its delay slots
+	pub fn falls_through(&self) -> bool {
+		!matches!(self.branch, BranchKind::Jump(_) | BranchKind::Return)
+	}
+}
+
+
+
+/// Architecture-defined register identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RegisterId {
+	pub id: u16,
+	pub name: &'static str,
+}
+
+impl fmt::Display for RegisterId {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.name)
+	}
+}
+
+
+
+/// Register update performed by a memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryUpdate {
+	None,
+	PreDecrement,	// @-Rn
+	PostIncrement,	// @Rn+
+}
+
+
+
+/// Memory operand. The effective address is `base + index + displacement`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryOperand {
+	/// Base register. If there is no base or index, `displacement` is an absolute address
+	pub base: Option<RegisterId>,
+	pub index: Option<RegisterId>,
+	/// Displacement in bytes
+	pub displacement: isize,
+	/// Size of the access in bytes
+	pub size: usize,
+	pub update: MemoryUpdate,
+	/// Whether the memory is read, written or both
+	pub access: SectionFlags,
+}
+
+
+
+/// Value copied into a register by a move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveSource {
+	Constant(u64),
+	Register(RegisterId),
+}
+
+
+
+/// Registers holding known constants within a sequence of instructions
+#[derive(Debug, Clone, Default)]
+pub struct Constants {
+	values: BTreeMap<RegisterId, u64>,
+	clobber: Option<usize>, // Instructions left until a call clobbers the values
+}
+
+
+
+/// Decoded instruction operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+	Register(RegisterId),
+	Immediate(i64),
+	Memory(MemoryOperand),
+	Target(usize), // Resolved PC-relative address (branch target or `mova`)
+}
+
+
+
+/// PC-relative operand resolved to an absolute address
+#[derive(Debug)]
+pub struct Literal {
+	/// Effective address of the operand
+	pub address: usize,
+	/// Value loaded from the address. None if the instruction does not
+	/// load from it (e.g. `mova`) or the memory is not mapped
+	pub value: Option<u64>,
+}
+
+
+
+impl Instruction {
+	/// Returns the opcode mnemonic
+	pub fn opcode(&self) -> &'static str {
+		match self.tokens.first() {
+			Some(Token {base: TokenBase::Opcode(op), ..}) => op,
+			_ => "",
+		}
+	}
+
+	/// Returns the operands in the order they are written
+	pub fn operands(&self) -> &[Operand] {
+		&self.operands
+	}
+
+	/// Returns the resolved PC-relative operand, if the instruction has one
+	pub fn literal(&self) -> Option<&Literal> {
+		self.literal.as_ref()
+	}
+
+	/// Returns the control flow metadata
+	pub fn info(&self) -> &InstructionInfo {
+		&self.info
+	}
+
+	/// Writes the tokens, substituting symbol names for addresses if `symbols` is given
+	fn write(&self, f: &mut fmt::Formatter, symbols: Option<&Symbols>) -> fmt::Result {
+		let name = |address: usize| symbols.and_then(|symbols| symbols.name(address));
+
 		for (i, token) in self.tokens.iter().enumerate() {
 			if i > 1 {
 				write!(f, ", ")?;
@@ -35,23 +236,323 @@ impl fmt::Display for Instruction {
 				TokenBase::Immediate(num) => {
 					write!(f, "{}{}{}", token.prefix, num, token.suffix)?;
 				}
+				TokenBase::SignedImmediate(num) => {
+					write!(f, "{}{}{}", token.prefix, num, token.suffix)?;
+				}
+				TokenBase::Address(address) => match name(address) {
+					Some(name) => write!(f, "{}{}{}", token.prefix, name, token.suffix)?,
+					None => write!(f, "{}0x{:X}{}", token.prefix, address, token.suffix)?,
+				},
 				TokenBase::Register(reg) => {
 					write!(f, "{}{}{}", token.prefix, reg, token.suffix)?;
 				}
 			};
 		}
+		if let Some(Literal {value: Some(value), ..}) = self.literal {
+			match name(value as usize) {
+				Some(name) => write!(f, " ; ={}", name)?,
+				None => write!(f, " ; =0x{:X}", value)?,
+			}
+		}
 		Ok(())
 	}
+
+	/// Returns a displayable form that shows symbol names in place of addresses
+	pub fn display<'a>(&'a self, symbols: &'a Symbols) -> InstructionDisplay<'a> {
+		InstructionDisplay {
+			instruction: self,
+			symbols,
+		}
+	}
+}
+
+
+impl Disassembly {
+	pub fn new() -> Disassembly {
+		Disassembly::default()
+	}
+
+	/// Queues an address for traversal by the next call to `analyze`.
+	/// Returns false if it is already an entry point.
+	pub fn add_entry_point(&mut self, address: usize) -> bool {
+		if !self.entry_points.insert(address) {
+			return false;
+		}
+		self.worklist.push(address);
+		true
+	}
+
+	/// Traverses the queued addresses, following fallthroughs, branches and calls.
+	/// Instructions decoded by previous calls are not decoded again.
+	pub fn analyze(&mut self, arch: &dyn Architecture, layout: &Layout) {
+		while let Some(address) = self.worklist.pop() {
+			self.trace(arch, layout, address);
+		}
+	}
+
+	/// Decodes instructions starting at the address until control flow leaves the sequence
+	fn trace(&mut self, arch: &dyn Architecture, layout: &Layout, start: usize) {
+		let mut address = start;
+		// Delay slots left before the sequence ends
+		let mut remaining: Option<usize> = None;
+		// Delay slots left of the current branch. Branches in a delay slot are not followed
+		let mut in_delay_slot = 0;
+		// Registers set earlier in the sequence, used to resolve `jsr @Rn`
+		let mut constants = Constants::new();
+
+		loop {
+			if remaining == Some(0) || self.instructions.contains_key(&address) || self.invalid.contains(&address) {
+				break;
+			}
+
+			let (instruction, size) = match arch.disassemble_single(layout, address) {
+				Ok(result) => result,
+				Err(_) => {
+					self.invalid.insert(address);
+					break;
+				}
+			};
+			let info = *instruction.info();
+			let current = address;
+			address += size;
+			remaining = remaining.map(|count| count - 1);
+
+			if in_delay_slot > 0 {
+				in_delay_slot -= 1;
+			} else {
+				let target = match info.target() {
+					Some(BranchTarget::Direct(target)) => Some(target),
+					Some(BranchTarget::Register(register)) => {
+						let target = constants.get(register).map(|value| value as usize);
+						if let Some(target) = target {
+							self.resolved.insert(current, target);
+						}
+						target
+					}
+					_ => None,
+				};
+				if let Some(target) = target {
+					self.worklist.push(target);
+					if let BranchKind::Call(_) = info.branch {
+						self.calls.insert(target);
+					}
+				}
+
+				if !info.falls_through() {
+					remaining = Some(info.delay_slots);
+				}
+				in_delay_slot = info.delay_slots;
+			}
+
+			if let Some(Literal {address: literal, value: Some(_)}) = instruction.literal() {
+				let size = instruction.operands().iter().find_map(|operand| match operand {
+					Operand::Memory(memory) => Some(memory.size),
+					_ => None,
+				});
+				if let Some(size) = size {
+					self.literals.insert(*literal, size);
+				}
+			}
+
+			constants.update(arch, &instruction);
+			self.instructions.insert(current, (instruction, size));
+		}
+	}
+
+	/// Returns the decoded instructions and their sizes ordered by address
+	pub fn instructions(&self) -> impl Iterator<Item = (usize, &Instruction, usize)> {
+		self.instructions.iter().map(|(address, (instruction, size))| (*address, instruction, *size))
+	}
+
+	/// Returns the instruction starting at the address
+	pub fn instruction_at(&self, address: usize) -> Option<&Instruction> {
+		self.instructions.get(&address).map(|(instruction, _)| instruction)
+	}
+
+	/// Returns the instruction starting at the address and its size
+	pub fn get(&self, address: usize) -> Option<(&Instruction, usize)> {
+		self.instructions.get(&address).map(|(instruction, size)| (instruction, *size))
+	}
+
+	/// Returns the destination of the branch at the address, if it is known.
+	/// Register branches are resolved when the register was loaded from a literal.
+	pub fn branch_target(&self, address: usize) -> Option<usize> {
+		match self.instruction_at(address)?.info().target()? {
+			BranchTarget::Direct(target) => Some(target),
+			_ => self.resolved.get(&address).cloned(),
+		}
+	}
+
+	/// Returns the targets of direct and resolved calls
+	pub fn call_targets(&self) -> impl Iterator<Item = usize> + '_ {
+		self.calls.iter().cloned()
+	}
+
+	/// Returns true if the address belongs to a decoded instruction
+	pub fn is_code(&self, address: usize) -> bool {
+		match self.instructions.range(..=address).next_back() {
+			Some((start, (_, size))) => address < start + size,
+			None => false,
+		}
+	}
+
+	/// Returns true if the address belongs to a literal loaded by a decoded instruction
+	pub fn is_literal(&self, address: usize) -> bool {
+		match self.literals.range(..=address).next_back() {
+			Some((start, size)) => address < start + size,
+			None => false,
+		}
+	}
+
+	pub fn entry_points(&self) -> impl Iterator<Item = usize> + '_ {
+		self.entry_points.iter().cloned()
+	}
+
+	/// Returns the reached addresses that could not be decoded
+	pub fn invalid(&self) -> impl Iterator<Item = usize> + '_ {
+		self.invalid.iter().cloned()
+	}
+
+	pub fn len(&self) -> usize {
+		self.instructions.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.instructions.is_empty()
+	}
+}
+
+
+
+impl Constants {
+	pub fn new() -> Constants {
+		Constants::default()
+	}
+
+	/// Returns the value of the register, if it is known
+	pub fn get(&self, register: RegisterId) -> Option<u64> {
+		self.values.get(&register).cloned()
+	}
+
+	pub fn set(&mut self, register: RegisterId, value: Option<u64>) {
+		match value {
+			Some(value) => self.values.insert(register, value),
+			None => self.values.remove(&register),
+		};
+	}
+
+	/// Applies the register writes of the instruction. Registers written
+	/// by anything but a move of a known value become unknown.
+	pub fn update(&mut self, arch: &dyn Architecture, instruction: &Instruction) {
+		if let BranchKind::Call(_) = instruction.info().branch {
+			self.clobber = Some(instruction.info().delay_slots);
+		}
+
+		for operand in instruction.operands() {
+			if let Operand::Memory(MemoryOperand {base: Some(base), update, ..}) = operand {
+				if *update != MemoryUpdate::None {
+					self.values.remove(base);
+				}
+			}
+		}
+
+		match arch.register_move(instruction) {
+			Some((destination, MoveSource::Constant(value))) => self.set(destination, Some(value)),
+			Some((destination, MoveSource::Register(source))) => self.set(destination, self.get(source)),
+			// The destination is written as the last operand
+			None => if let Some(Operand::Register(register)) = instruction.operands().last() {
+				self.values.remove(register);
+			},
+		}
+
+		// The called function may change any register once the delay slots have executed
+		self.clobber = match self.clobber {
+			Some(0) => {
+				self.values.clear();
+				None
+			}
+			count => count.map(|count| count - 1),
+		};
+	}
+
+	/// Returns the effective address of the memory operand, if it is known
+	pub fn address(&self, operand: &MemoryOperand) -> Option<usize> {
+		let mut address = operand.displacement as u64;
+		if let Some(base) = operand.base {
+			address = address.wrapping_add(self.get(base)?);
+		}
+		if let Some(index) = operand.index {
+			address = address.wrapping_add(self.get(index)?);
+		}
+		if operand.update == MemoryUpdate::PreDecrement {
+			address = address.wrapping_sub(operand.size as u64);
+		}
+		Some(address as usize)
+	}
+}
+
+
+
+impl fmt::Display for Instruction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.write(f, None)
+	}
+}
+
+
+
+/// Instruction formatted with symbol names, see `Instruction::display`
+pub struct InstructionDisplay<'a> {
+	instruction: &'a Instruction,
+	symbols: &'a Symbols,
+}
+
+impl fmt::Display for InstructionDisplay<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.instruction.write(f, Some(self.symbols))
+	}
 }
 
 
 
 pub trait Architecture {
 	/// Disassembles a single instruction or returns an error.
-	/// Returns the amount of bytes used.
+	/// Returns the amount of bytes used. Branches, delay slots and
+	/// slot-illegal instructions are reported through `Instruction::info`.
 	fn disassemble_single(&self, layout: &Layout, address: usize) -> Result<(Instruction, usize)>;
 
+	/// Translates an instruction decoded at the address into IL statements, in execution order.
+	/// Instructions without an effect produce no statements.
+	fn lift(&self, instruction: &Instruction, address: usize) -> Vec<il::InstructionTree>;
+
+	/// Returns the register adjusted by the IL `Push` and `Pop`
+	fn stack_pointer(&self) -> Option<RegisterId> {
+		None
+	}
+
+	/// Returns the registers preserved by called functions. Calls change every other register and flag.
+	fn callee_saved(&self) -> Vec<RegisterId> {
+		Vec::new()
+	}
+
 	// Analyzer
+
+	/// Reads the exception vector table starting at `base`. Handler entries
+	/// that do not point to executable memory are left out.
+	fn vector_table(&self, _layout: &Layout, _base: usize) -> Vec<Vector> {
+		Vec::new()
+	}
+
+	/// Scans executable memory for function prologues. Returns the addresses the functions start at.
+	/// Memory the disassembly has decoded as literals is skipped.
+	fn find_prologues(&self, _layout: &Layout, _disassembly: &Disassembly) -> Vec<usize> {
+		Vec::new()
+	}
+
+	/// Returns the destination and source if the instruction only copies a constant or register into a register
+	fn register_move(&self, _instruction: &Instruction) -> Option<(RegisterId, MoveSource)> {
+		None
+	}
 }
 
 
@@ -60,6 +561,8 @@ pub trait Architecture {
 pub enum TokenBase {
 	Opcode(&'static str),
 	Immediate(usize),
+	SignedImmediate(isize),
+	Address(usize),
 	Register(&'static str),
 }
 
@@ -90,4 +593,65 @@ impl Token {
 		self.suffix = suffix;
 		self
 	}
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::code_layout;
+
+	#[test]
+	fn recursive_descent() {
+		// 1000: bsr 100C; nop; bra 1010; nop; data; data
+		// 100C: rts; nop
+		// 1010: bt 1016; jmp @R1; nop
+		// 1016: rts; nop; data
+		let layout = code_layout(&[0xB004, 0x0009, 0xA004, 0x0009, 0xFFFF, 0xFFFF, 0x000B, 0x0009, 0x8901, 0x412B, 0x0009, 0x000B, 0x0009, 0xFFFF]);
+		let arch = sh2e::SH2E::new();
+		let mut disassembly = Disassembly::new();
+		assert!(disassembly.add_entry_point(0x1000));
+		disassembly.analyze(&arch, &layout);
+
+		let addresses: Vec<usize> = disassembly.instructions().map(|(address, _, _)| address).collect();
+		assert_eq!(addresses, vec![0x1000, 0x1002, 0x1004, 0x1006, 0x100C, 0x100E, 0x1010, 0x1012, 0x1014, 0x1016, 0x1018]);
+		assert_eq!(disassembly.call_targets().collect::<Vec<_>>(), vec![0x100C]);
+		assert_eq!(disassembly.branch_target(0x1004), Some(0x1010));
+		assert_eq!(disassembly.branch_target(0x1012), None);
+		assert!(!disassembly.is_code(0x1008) && disassembly.is_code(0x1019) && !disassembly.is_code(0x101A));
+		assert_eq!(disassembly.invalid().count(), 0);
+
+		// New entry points only traverse what was not decoded yet
+		assert!(!disassembly.add_entry_point(0x1000));
+		assert!(disassembly.add_entry_point(0x1008));
+		disassembly.analyze(&arch, &layout);
+		assert_eq!(disassembly.len(), 11);
+		assert_eq!(disassembly.invalid().collect::<Vec<_>>(), vec![0x1008]);
+		assert_eq!(disassembly.entry_points().collect::<Vec<_>>(), vec![0x1000, 0x1008]);
+	}
+
+	#[test]
+	fn register_call() {
+		// 1000: mov.l @(1008),R1; jsr @R1; nop; nop; .long 100C
+		// 100C: rts; nop
+		let layout = code_layout(&[0xD101, 0x410B, 0x0009, 0x0009, 0x0000, 0x100C, 0x000B, 0x0009]);
+		let arch = sh2e::SH2E::new();
+		let mut disassembly = Disassembly::new();
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&arch, &layout);
+
+		assert_eq!(disassembly.branch_target(0x1002), Some(0x100C));
+		assert_eq!(disassembly.call_targets().collect::<Vec<_>>(), vec![0x100C]);
+		assert!(disassembly.is_code(0x1006) && !disassembly.is_code(0x1008) && disassembly.is_code(0x100E));
+
+		// The call clobbers R2 after it was loaded in the delay slot
+		// 1000: mov.l @(1010),R1; jsr @R1; mov.l @(1014),R2; jsr @R2; nop; rts; nop; nop; .long 1018; .long 1018
+		// 1018: rts; nop
+		let layout = code_layout(&[0xD103, 0x410B, 0xD202, 0x420B, 0x0009, 0x000B, 0x0009, 0x0009, 0x0000, 0x1018, 0x0000, 0x1018, 0x000B, 0x0009]);
+		let mut disassembly = Disassembly::new();
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&arch, &layout);
+		assert_eq!(disassembly.branch_target(0x1002), Some(0x1018));
+		assert_eq!(disassembly.branch_target(0x1006), None);
+	}
+}
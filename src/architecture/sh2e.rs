@@ -1,10 +1,14 @@
 use smallvec::SmallVec;
-use crate::memory::Layout;
-use crate::architecture::{Architecture, Token, TokenBase, Instruction};
+use crate::memory::{Layout, SectionFlags};
+use crate::architecture::{Architecture, Disassembly, Token, TokenBase, Instruction, InstructionInfo, BranchKind, BranchTarget, Literal};
+use crate::architecture::{Operand, MemoryOperand, MemoryUpdate, MoveSource, RegisterId, Vector};
 use crate::error::{Error, Result};
+use crate::il::InstructionTree;
 
 use std::fmt;
 
+mod lift;
+
 
 
 /// Renesas SH2E architecture
@@ -15,6 +19,7 @@ pub struct SH2E {
 
 
 
+#[allow(dead_code)]
 enum SuperHNibble {
 	InstructionCode(u8),
 	RegSrc,
@@ -25,6 +30,7 @@ enum SuperHNibble {
 
 
 
+#[allow(clippy::upper_case_acronyms)]
 enum SuperHFormat {
 	Zero(u16),	// xxxx xxxx xxxx xxxx
 	N(u8, u8),	// xxxx nnnn xxxx xxxx
@@ -42,6 +48,8 @@ enum SuperHFormat {
 
 
 
+#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 enum Register {
 	R0,
 	R1,
@@ -59,8 +67,32 @@ enum Register {
 	R13,
 	R14,
 	R15,
+	SR,
 	GBR,
+	VBR,
+	MACH,
+	MACL,
+	PR,
+	#[allow(dead_code)]
 	PC,
+	FR0,
+	FR1,
+	FR2,
+	FR3,
+	FR4,
+	FR5,
+	FR6,
+	FR7,
+	FR8,
+	FR9,
+	FR10,
+	FR11,
+	FR12,
+	FR13,
+	FR14,
+	FR15,
+	FPUL,
+	FPSCR,
 }
 
 impl Register {
@@ -82,12 +114,83 @@ impl Register {
 			Register::R13 => "R13",
 			Register::R14 => "R14",
 			Register::R15 => "R15",
+			Register::SR => "SR",
 			Register::GBR => "GBR",
+			Register::VBR => "VBR",
+			Register::MACH => "MACH",
+			Register::MACL => "MACL",
+			Register::PR => "PR",
 			Register::PC => "PC",
+			Register::FR0 => "FR0",
+			Register::FR1 => "FR1",
+			Register::FR2 => "FR2",
+			Register::FR3 => "FR3",
+			Register::FR4 => "FR4",
+			Register::FR5 => "FR5",
+			Register::FR6 => "FR6",
+			Register::FR7 => "FR7",
+			Register::FR8 => "FR8",
+			Register::FR9 => "FR9",
+			Register::FR10 => "FR10",
+			Register::FR11 => "FR11",
+			Register::FR12 => "FR12",
+			Register::FR13 => "FR13",
+			Register::FR14 => "FR14",
+			Register::FR15 => "FR15",
+			Register::FPUL => "FPUL",
+			Register::FPSCR => "FPSCR",
+		}
+	}
+
+	/// Returns the architecture-independent identifier
+	fn id(self) -> RegisterId {
+		RegisterId {id: self as u16, name: self.static_str()}
+	}
+
+	/// Returns the floating point register with the same number as a general register
+	fn as_float(&self) -> Register {
+		match *self {
+			Register::R0 => Register::FR0,
+			Register::R1 => Register::FR1,
+			Register::R2 => Register::FR2,
+			Register::R3 => Register::FR3,
+			Register::R4 => Register::FR4,
+			Register::R5 => Register::FR5,
+			Register::R6 => Register::FR6,
+			Register::R7 => Register::FR7,
+			Register::R8 => Register::FR8,
+			Register::R9 => Register::FR9,
+			Register::R10 => Register::FR10,
+			Register::R11 => Register::FR11,
+			Register::R12 => Register::FR12,
+			Register::R13 => Register::FR13,
+			Register::R14 => Register::FR14,
+			Register::R15 => Register::FR15,
+			_ => Register::FR0,
 		}
 	}
 }
 
+/// Status register bits tracked as IL flags
+#[derive(Clone, Copy)]
+enum Flag {
+	T,	// True/false condition and carry
+	Q,	// Division state
+	M,	// Division state
+}
+
+impl Flag {
+	/// Returns the identifier, numbered after the registers
+	fn id(self) -> RegisterId {
+		let name = match self {
+			Flag::T => "T",
+			Flag::Q => "Q",
+			Flag::M => "M",
+		};
+		RegisterId {id: Register::FPSCR as u16 + 1 + self as u16, name}
+	}
+}
+
 impl fmt::Display for Register {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.static_str())
@@ -123,13 +226,19 @@ impl From<u8> for Register {
 
 enum ArgumentType {
 	Immediate,					// #imm
+	SignedImmediate,			// #imm				Sign-extended immediate
+	DirectFixedReg(Register),	// R0, SR, GBR...	Register implied by the opcode
 	DirectDestReg,				// Rn
 	DirectSrcReg,				// Rm
+	FloatDestReg,				// FRn
+	FloatSrcReg,				// FRm
 	IndirectDestReg,			// @Rn
 	IndirectSrcReg,				// @Rm
+	IndirectJumpReg,			// @Rm				Jump target held in Rm
 	PostIncIndirectDestReg,		// @Rn+				Post-increment indirect register
 	PreDecIndirectDestReg,		// @-Rn 			Pre-decrement indirect register
 	PostIncIndirectSrcReg,		// @Rm+				Post-increment indirect register
+	#[allow(dead_code)]
 	PreDecIndirectSrcReg,		// @-Rm 			Pre-decrement indirect register
 	IndirectDestRegDisp,		// @(disp:4, Rn)
 	IndirectSrcRegDisp,			// @(disp:4, Rm)
@@ -137,7 +246,9 @@ enum ArgumentType {
 	IndirectIdxSrcReg,			// @(R0, Rm)
 	IndirectGbrDisp,			// @(disp:8, GBR)
 	IndirectIdxGbr,				// @(R0, GBR)
-	IndirectPcDisp,				// @(disp:8/12, PC)
+	IndirectPcDisp,				// @(disp:8, PC)		Resolved to the literal pool address and value
+	PcRelativeAddress,			// @(disp:8, PC)		Resolved to an address without loading from it
+	BranchTarget,				// disp:8/12		Resolved to PC + 4 + disp * 2
 }
 
 
@@ -151,12 +262,12 @@ struct SuperHInstruction {
 
 
 // Instructions
-const INSTRUCTIONS: &'static [SuperHInstruction] = &[
+const INSTRUCTIONS: &[SuperHInstruction] = &[
 	SuperHInstruction {opcode: "nop", format: SuperHFormat::Zero(0b1001), arguments: &[]},
 
-	SuperHInstruction {opcode: "mov", format: SuperHFormat::NI(0b1110), arguments: &[ArgumentType::Immediate, ArgumentType::DirectDestReg]},
-	SuperHInstruction {opcode: "mov.w", format: SuperHFormat::NI(0b1001), arguments: &[ArgumentType::IndirectPcDisp, ArgumentType::DirectDestReg]},
-	SuperHInstruction {opcode: "mov.l", format: SuperHFormat::NI(0b1101), arguments: &[ArgumentType::IndirectPcDisp, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "mov", format: SuperHFormat::NI(0b1110), arguments: &[ArgumentType::SignedImmediate, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "mov.w", format: SuperHFormat::ND8(0b1001), arguments: &[ArgumentType::IndirectPcDisp, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "mov.l", format: SuperHFormat::ND8(0b1101), arguments: &[ArgumentType::IndirectPcDisp, ArgumentType::DirectDestReg]},
 
 	SuperHInstruction {opcode: "mov", format: SuperHFormat::NM(0b0110, 0b0011), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
 	SuperHInstruction {opcode: "mov.b", format: SuperHFormat::NM(0b0010, 0b0000), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::IndirectDestReg]},
@@ -199,8 +310,165 @@ const INSTRUCTIONS: &'static [SuperHInstruction] = &[
 	SuperHInstruction {opcode: "mov.w", format: SuperHFormat::D(0b11000101), arguments: &[ArgumentType::IndirectGbrDisp, ArgumentType::DirectDestReg]}, // Rn = R0
 	SuperHInstruction {opcode: "mov.l", format: SuperHFormat::D(0b11000110), arguments: &[ArgumentType::IndirectGbrDisp, ArgumentType::DirectDestReg]}, // Rn = R0
 
-	SuperHInstruction {opcode: "mova", format: SuperHFormat::D(0b11000111), arguments: &[ArgumentType::IndirectPcDisp, ArgumentType::DirectDestReg]}, // Rn = R0
+	SuperHInstruction {opcode: "mova", format: SuperHFormat::D(0b11000111), arguments: &[ArgumentType::PcRelativeAddress, ArgumentType::DirectDestReg]}, // Rn = R0
 	SuperHInstruction {opcode: "movt", format: SuperHFormat::N(0b0000, 0b00101001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "swap.b", format: SuperHFormat::NM(0b0110, 0b1000), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "swap.w", format: SuperHFormat::NM(0b0110, 0b1001), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "xtrct", format: SuperHFormat::NM(0b0010, 0b1101), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+
+	// Arithmetic
+	SuperHInstruction {opcode: "add", format: SuperHFormat::NM(0b0011, 0b1100), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "add", format: SuperHFormat::NI(0b0111), arguments: &[ArgumentType::SignedImmediate, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "addc", format: SuperHFormat::NM(0b0011, 0b1110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "addv", format: SuperHFormat::NM(0b0011, 0b1111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sub", format: SuperHFormat::NM(0b0011, 0b1000), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "subc", format: SuperHFormat::NM(0b0011, 0b1010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "subv", format: SuperHFormat::NM(0b0011, 0b1011), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "neg", format: SuperHFormat::NM(0b0110, 0b1011), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "negc", format: SuperHFormat::NM(0b0110, 0b1010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "dt", format: SuperHFormat::N(0b0100, 0b00010000), arguments: &[ArgumentType::DirectDestReg]},
+
+	SuperHInstruction {opcode: "cmp/eq", format: SuperHFormat::I(0b10001000), arguments: &[ArgumentType::SignedImmediate, ArgumentType::DirectFixedReg(Register::R0)]},
+	SuperHInstruction {opcode: "cmp/eq", format: SuperHFormat::NM(0b0011, 0b0000), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/hs", format: SuperHFormat::NM(0b0011, 0b0010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/ge", format: SuperHFormat::NM(0b0011, 0b0011), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/hi", format: SuperHFormat::NM(0b0011, 0b0110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/gt", format: SuperHFormat::NM(0b0011, 0b0111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/pz", format: SuperHFormat::N(0b0100, 0b00010001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/pl", format: SuperHFormat::N(0b0100, 0b00010101), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "cmp/str", format: SuperHFormat::NM(0b0010, 0b1100), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+
+	SuperHInstruction {opcode: "div1", format: SuperHFormat::NM(0b0011, 0b0100), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "div0s", format: SuperHFormat::NM(0b0010, 0b0111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "div0u", format: SuperHFormat::Zero(0b0000000000011001), arguments: &[]},
+
+	SuperHInstruction {opcode: "mul.l", format: SuperHFormat::NM(0b0000, 0b0111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "muls.w", format: SuperHFormat::NM(0b0010, 0b1111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "mulu.w", format: SuperHFormat::NM(0b0010, 0b1110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "dmuls.l", format: SuperHFormat::NM(0b0011, 0b1101), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "dmulu.l", format: SuperHFormat::NM(0b0011, 0b0101), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "mac.l", format: SuperHFormat::NM(0b0000, 0b1111), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::PostIncIndirectDestReg]},
+	SuperHInstruction {opcode: "mac.w", format: SuperHFormat::NM(0b0100, 0b1111), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::PostIncIndirectDestReg]},
+
+	SuperHInstruction {opcode: "exts.b", format: SuperHFormat::NM(0b0110, 0b1110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "exts.w", format: SuperHFormat::NM(0b0110, 0b1111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "extu.b", format: SuperHFormat::NM(0b0110, 0b1100), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "extu.w", format: SuperHFormat::NM(0b0110, 0b1101), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+
+	// Logic
+	SuperHInstruction {opcode: "and", format: SuperHFormat::NM(0b0010, 0b1001), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "and", format: SuperHFormat::I(0b11001001), arguments: &[ArgumentType::Immediate, ArgumentType::DirectFixedReg(Register::R0)]},
+	SuperHInstruction {opcode: "and.b", format: SuperHFormat::I(0b11001101), arguments: &[ArgumentType::Immediate, ArgumentType::IndirectIdxGbr]},
+	SuperHInstruction {opcode: "or", format: SuperHFormat::NM(0b0010, 0b1011), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "or", format: SuperHFormat::I(0b11001011), arguments: &[ArgumentType::Immediate, ArgumentType::DirectFixedReg(Register::R0)]},
+	SuperHInstruction {opcode: "or.b", format: SuperHFormat::I(0b11001111), arguments: &[ArgumentType::Immediate, ArgumentType::IndirectIdxGbr]},
+	SuperHInstruction {opcode: "xor", format: SuperHFormat::NM(0b0010, 0b1010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "xor", format: SuperHFormat::I(0b11001010), arguments: &[ArgumentType::Immediate, ArgumentType::DirectFixedReg(Register::R0)]},
+	SuperHInstruction {opcode: "xor.b", format: SuperHFormat::I(0b11001110), arguments: &[ArgumentType::Immediate, ArgumentType::IndirectIdxGbr]},
+	SuperHInstruction {opcode: "tst", format: SuperHFormat::NM(0b0010, 0b1000), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "tst", format: SuperHFormat::I(0b11001000), arguments: &[ArgumentType::Immediate, ArgumentType::DirectFixedReg(Register::R0)]},
+	SuperHInstruction {opcode: "tst.b", format: SuperHFormat::I(0b11001100), arguments: &[ArgumentType::Immediate, ArgumentType::IndirectIdxGbr]},
+	SuperHInstruction {opcode: "not", format: SuperHFormat::NM(0b0110, 0b0111), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "tas.b", format: SuperHFormat::N(0b0100, 0b00011011), arguments: &[ArgumentType::IndirectDestReg]},
+
+	// Shifts and rotates
+	SuperHInstruction {opcode: "rotl", format: SuperHFormat::N(0b0100, 0b00000100), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "rotr", format: SuperHFormat::N(0b0100, 0b00000101), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "rotcl", format: SuperHFormat::N(0b0100, 0b00100100), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "rotcr", format: SuperHFormat::N(0b0100, 0b00100101), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shal", format: SuperHFormat::N(0b0100, 0b00100000), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shar", format: SuperHFormat::N(0b0100, 0b00100001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shll", format: SuperHFormat::N(0b0100, 0b00000000), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shlr", format: SuperHFormat::N(0b0100, 0b00000001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shll2", format: SuperHFormat::N(0b0100, 0b00001000), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shlr2", format: SuperHFormat::N(0b0100, 0b00001001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shll8", format: SuperHFormat::N(0b0100, 0b00011000), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shlr8", format: SuperHFormat::N(0b0100, 0b00011001), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shll16", format: SuperHFormat::N(0b0100, 0b00101000), arguments: &[ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "shlr16", format: SuperHFormat::N(0b0100, 0b00101001), arguments: &[ArgumentType::DirectDestReg]},
+
+	// System control
+	SuperHInstruction {opcode: "clrt", format: SuperHFormat::Zero(0b0000000000001000), arguments: &[]},
+	SuperHInstruction {opcode: "sett", format: SuperHFormat::Zero(0b0000000000011000), arguments: &[]},
+	SuperHInstruction {opcode: "clrmac", format: SuperHFormat::Zero(0b0000000000101000), arguments: &[]},
+	SuperHInstruction {opcode: "sleep", format: SuperHFormat::Zero(0b0000000000011011), arguments: &[]},
+
+	SuperHInstruction {opcode: "ldc", format: SuperHFormat::M(0b0100, 0b00001110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::SR)]},
+	SuperHInstruction {opcode: "ldc.l", format: SuperHFormat::M(0b0100, 0b00000111), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::SR)]},
+	SuperHInstruction {opcode: "ldc", format: SuperHFormat::M(0b0100, 0b00011110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::GBR)]},
+	SuperHInstruction {opcode: "ldc.l", format: SuperHFormat::M(0b0100, 0b00010111), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::GBR)]},
+	SuperHInstruction {opcode: "ldc", format: SuperHFormat::M(0b0100, 0b00101110), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::VBR)]},
+	SuperHInstruction {opcode: "ldc.l", format: SuperHFormat::M(0b0100, 0b00100111), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::VBR)]},
+	SuperHInstruction {opcode: "lds", format: SuperHFormat::M(0b0100, 0b00001010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::MACH)]},
+	SuperHInstruction {opcode: "lds.l", format: SuperHFormat::M(0b0100, 0b00000110), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::MACH)]},
+	SuperHInstruction {opcode: "lds", format: SuperHFormat::M(0b0100, 0b00011010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::MACL)]},
+	SuperHInstruction {opcode: "lds.l", format: SuperHFormat::M(0b0100, 0b00010110), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::MACL)]},
+	SuperHInstruction {opcode: "lds", format: SuperHFormat::M(0b0100, 0b00101010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::PR)]},
+	SuperHInstruction {opcode: "lds.l", format: SuperHFormat::M(0b0100, 0b00100110), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::PR)]},
+
+	SuperHInstruction {opcode: "stc", format: SuperHFormat::N(0b0000, 0b00000010), arguments: &[ArgumentType::DirectFixedReg(Register::SR), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "stc.l", format: SuperHFormat::N(0b0100, 0b00000011), arguments: &[ArgumentType::DirectFixedReg(Register::SR), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "stc", format: SuperHFormat::N(0b0000, 0b00010010), arguments: &[ArgumentType::DirectFixedReg(Register::GBR), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "stc.l", format: SuperHFormat::N(0b0100, 0b00010011), arguments: &[ArgumentType::DirectFixedReg(Register::GBR), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "stc", format: SuperHFormat::N(0b0000, 0b00100010), arguments: &[ArgumentType::DirectFixedReg(Register::VBR), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "stc.l", format: SuperHFormat::N(0b0100, 0b00100011), arguments: &[ArgumentType::DirectFixedReg(Register::VBR), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "sts", format: SuperHFormat::N(0b0000, 0b00001010), arguments: &[ArgumentType::DirectFixedReg(Register::MACH), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sts.l", format: SuperHFormat::N(0b0100, 0b00000010), arguments: &[ArgumentType::DirectFixedReg(Register::MACH), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "sts", format: SuperHFormat::N(0b0000, 0b00011010), arguments: &[ArgumentType::DirectFixedReg(Register::MACL), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sts.l", format: SuperHFormat::N(0b0100, 0b00010010), arguments: &[ArgumentType::DirectFixedReg(Register::MACL), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "sts", format: SuperHFormat::N(0b0000, 0b00101010), arguments: &[ArgumentType::DirectFixedReg(Register::PR), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sts.l", format: SuperHFormat::N(0b0100, 0b00100010), arguments: &[ArgumentType::DirectFixedReg(Register::PR), ArgumentType::PreDecIndirectDestReg]},
+
+
+	// Branches
+	SuperHInstruction {opcode: "bra", format: SuperHFormat::D12(0b1010), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "bsr", format: SuperHFormat::D12(0b1011), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "bt", format: SuperHFormat::D(0b10001001), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "bf", format: SuperHFormat::D(0b10001011), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "bt/s", format: SuperHFormat::D(0b10001101), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "bf/s", format: SuperHFormat::D(0b10001111), arguments: &[ArgumentType::BranchTarget]},
+	SuperHInstruction {opcode: "braf", format: SuperHFormat::M(0b0000, 0b00100011), arguments: &[ArgumentType::DirectSrcReg]},
+	SuperHInstruction {opcode: "bsrf", format: SuperHFormat::M(0b0000, 0b00000011), arguments: &[ArgumentType::DirectSrcReg]},
+	SuperHInstruction {opcode: "jmp", format: SuperHFormat::M(0b0100, 0b00101011), arguments: &[ArgumentType::IndirectJumpReg]},
+	SuperHInstruction {opcode: "jsr", format: SuperHFormat::M(0b0100, 0b00001011), arguments: &[ArgumentType::IndirectJumpReg]},
+	SuperHInstruction {opcode: "rts", format: SuperHFormat::Zero(0b0000000000001011), arguments: &[]},
+	SuperHInstruction {opcode: "rte", format: SuperHFormat::Zero(0b0000000000101011), arguments: &[]},
+	SuperHInstruction {opcode: "trapa", format: SuperHFormat::I(0b11000011), arguments: &[ArgumentType::Immediate]},
+
+	// Floating point unit
+	SuperHInstruction {opcode: "fmov", format: SuperHFormat::NM(0b1111, 0b1100), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b1000), arguments: &[ArgumentType::IndirectSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b1010), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::IndirectDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b1001), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b1011), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b0110), arguments: &[ArgumentType::IndirectIdxSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmov.s", format: SuperHFormat::NM(0b1111, 0b0111), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::IndirectIdxDestReg]},
+
+	SuperHInstruction {opcode: "fadd", format: SuperHFormat::NM(0b1111, 0b0000), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fsub", format: SuperHFormat::NM(0b1111, 0b0001), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmul", format: SuperHFormat::NM(0b1111, 0b0010), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fdiv", format: SuperHFormat::NM(0b1111, 0b0011), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fcmp/eq", format: SuperHFormat::NM(0b1111, 0b0100), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fcmp/gt", format: SuperHFormat::NM(0b1111, 0b0101), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fmac", format: SuperHFormat::NM(0b1111, 0b1110), arguments: &[ArgumentType::DirectFixedReg(Register::FR0), ArgumentType::FloatSrcReg, ArgumentType::FloatDestReg]},
+
+	SuperHInstruction {opcode: "fneg", format: SuperHFormat::N(0b1111, 0b01001101), arguments: &[ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fabs", format: SuperHFormat::N(0b1111, 0b01011101), arguments: &[ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fldi0", format: SuperHFormat::N(0b1111, 0b10001101), arguments: &[ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "fldi1", format: SuperHFormat::N(0b1111, 0b10011101), arguments: &[ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "float", format: SuperHFormat::N(0b1111, 0b00101101), arguments: &[ArgumentType::DirectFixedReg(Register::FPUL), ArgumentType::FloatDestReg]},
+	SuperHInstruction {opcode: "ftrc", format: SuperHFormat::M(0b1111, 0b00111101), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::DirectFixedReg(Register::FPUL)]},
+	SuperHInstruction {opcode: "flds", format: SuperHFormat::M(0b1111, 0b00011101), arguments: &[ArgumentType::FloatSrcReg, ArgumentType::DirectFixedReg(Register::FPUL)]},
+	SuperHInstruction {opcode: "fsts", format: SuperHFormat::N(0b1111, 0b00001101), arguments: &[ArgumentType::DirectFixedReg(Register::FPUL), ArgumentType::FloatDestReg]},
+
+	SuperHInstruction {opcode: "lds", format: SuperHFormat::M(0b0100, 0b01011010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::FPUL)]},
+	SuperHInstruction {opcode: "lds.l", format: SuperHFormat::M(0b0100, 0b01010110), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::FPUL)]},
+	SuperHInstruction {opcode: "lds", format: SuperHFormat::M(0b0100, 0b01101010), arguments: &[ArgumentType::DirectSrcReg, ArgumentType::DirectFixedReg(Register::FPSCR)]},
+	SuperHInstruction {opcode: "lds.l", format: SuperHFormat::M(0b0100, 0b01100110), arguments: &[ArgumentType::PostIncIndirectSrcReg, ArgumentType::DirectFixedReg(Register::FPSCR)]},
+	SuperHInstruction {opcode: "sts", format: SuperHFormat::N(0b0000, 0b01011010), arguments: &[ArgumentType::DirectFixedReg(Register::FPUL), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sts.l", format: SuperHFormat::N(0b0100, 0b01010010), arguments: &[ArgumentType::DirectFixedReg(Register::FPUL), ArgumentType::PreDecIndirectDestReg]},
+	SuperHInstruction {opcode: "sts", format: SuperHFormat::N(0b0000, 0b01101010), arguments: &[ArgumentType::DirectFixedReg(Register::FPSCR), ArgumentType::DirectDestReg]},
+	SuperHInstruction {opcode: "sts.l", format: SuperHFormat::N(0b0100, 0b01100010), arguments: &[ArgumentType::DirectFixedReg(Register::FPSCR), ArgumentType::PreDecIndirectDestReg]},
 ];
 
 
@@ -253,13 +521,59 @@ impl Nibbles {
 
 
 impl SuperHInstruction {
+	/// Returns the size of the memory access from the opcode suffix, or 0 if it has none
+	fn access_size(&self) -> usize {
+		if self.opcode.ends_with(".b") {
+			1
+		} else if self.opcode.ends_with(".w") {
+			2
+		} else if self.opcode.ends_with(".l") || self.opcode.ends_with(".s") {
+			4
+		} else {
+			0
+		}
+	}
+
+	/// Returns how the argument at the position accesses memory
+	fn memory_access(&self, position: usize) -> SectionFlags {
+		match self.opcode {
+			"tst.b" | "mac.w" | "mac.l" => SectionFlags::Read,
+			"and.b" | "or.b" | "xor.b" | "tas.b" => SectionFlags::ReadWrite,
+			// Otherwise the destination is the last argument
+			_ if position > 0 && position + 1 == self.arguments.len() => SectionFlags::Write,
+			_ => SectionFlags::Read,
+		}
+	}
+
+	/// Returns the control flow metadata. `target` is the resolved branch target, if any
+	fn info(&self, target: BranchTarget) -> InstructionInfo {
+		let (branch, delay_slots) = match self.opcode {
+			"bra" | "braf" | "jmp" => (BranchKind::Jump(target), 1),
+			"bsr" | "bsrf" | "jsr" => (BranchKind::Call(target), 1),
+			"bt" | "bf" => (BranchKind::ConditionalJump(target), 0),
+			"bt/s" | "bf/s" => (BranchKind::ConditionalJump(target), 1),
+			"rts" | "rte" => (BranchKind::Return, 1),
+			"trapa" => (BranchKind::Trap, 0),
+			_ => (BranchKind::None, 0),
+		};
+
+		InstructionInfo {
+			branch,
+			delay_slots,
+			// Every instruction that modifies PC is illegal in a delay slot
+			illegal_in_delay_slot: branch != BranchKind::None,
+		}
+	}
+
 	#[inline]
-	fn disassemble(&self, nibbles: &Nibbles) -> Option<SmallVec<[Token; 6]>> {
+	fn disassemble(&self, layout: &Layout, nibbles: &Nibbles, address: usize) -> Option<Instruction> {
 		// TODO: optimizations
 		let mut source_reg = Register::R0;
 		let mut dest_reg = Register::R0;
 		let mut immediate: usize = 0;
 		let mut displacement: usize = 0;
+		let mut literal = None;
+		let mut branch_target = BranchTarget::Indirect;
 
 		match self.format {
 			SuperHFormat::Zero(x) if nibbles.check_word(x) => {
@@ -310,116 +624,616 @@ impl SuperHInstruction {
 		};
 
 		let mut tokens = SmallVec::new();
+		let mut operands = SmallVec::new();
 
 		// Push the opcode
 		tokens.push(Token::new(TokenBase::Opcode(self.opcode)));
 
-		for arg in self.arguments {
+		// Memory operands are displaced in units of the access size
+		let size = self.access_size();
+		let scaled = displacement * size;
+		let memory = |base: Register, index: Option<Register>, displacement: usize, update: MemoryUpdate, access: SectionFlags| {
+			Operand::Memory(MemoryOperand {
+				base: Some(base.id()),
+				index: index.map(|index| index.id()),
+				displacement: displacement as isize,
+				size,
+				update,
+				access,
+			})
+		};
+
+		for (position, arg) in self.arguments.iter().enumerate() {
+			let access = self.memory_access(position);
 			match arg {
 				ArgumentType::Immediate => {
 					tokens.push(Token::new(TokenBase::Immediate(immediate)).with_prefix("#"));
+					operands.push(Operand::Immediate(immediate as i64));
+				}
+				ArgumentType::SignedImmediate => {
+					let immediate = immediate as u8 as i8;
+					tokens.push(Token::new(TokenBase::SignedImmediate(immediate as isize)).with_prefix("#"));
+					operands.push(Operand::Immediate(i64::from(immediate)));
+				}
+				ArgumentType::DirectFixedReg(reg) => {
+					tokens.push(Token::new(TokenBase::Register(reg.static_str())));
+					operands.push(Operand::Register(reg.id()));
 				}
 				ArgumentType::DirectDestReg => {
-					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())))
+					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())));
+					operands.push(Operand::Register(dest_reg.id()));
 				}
 				ArgumentType::DirectSrcReg => {
-					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())))
+					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())));
+					operands.push(Operand::Register(source_reg.id()));
+				}
+				ArgumentType::FloatDestReg => {
+					tokens.push(Token::new(TokenBase::Register(dest_reg.as_float().static_str())));
+					operands.push(Operand::Register(dest_reg.as_float().id()));
+				}
+				ArgumentType::FloatSrcReg => {
+					tokens.push(Token::new(TokenBase::Register(source_reg.as_float().static_str())));
+					operands.push(Operand::Register(source_reg.as_float().id()));
 				}
 				ArgumentType::IndirectDestReg => {
-					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@"))
+					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@"));
+					operands.push(memory(dest_reg, None, 0, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectSrcReg => {
-					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@"))
+					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@"));
+					operands.push(memory(source_reg, None, 0, MemoryUpdate::None, access));
+				}
+				ArgumentType::IndirectJumpReg => {
+					// The register holds the target; no memory is accessed
+					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@"));
+					operands.push(Operand::Register(source_reg.id()));
+					branch_target = BranchTarget::Register(source_reg.id());
 				}
 				ArgumentType::PostIncIndirectDestReg => {
-					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@").with_suffix("+"))
+					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@").with_suffix("+"));
+					operands.push(memory(dest_reg, None, 0, MemoryUpdate::PostIncrement, access));
 				}
 				ArgumentType::PostIncIndirectSrcReg => {
-					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@").with_suffix("+"))
+					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@").with_suffix("+"));
+					operands.push(memory(source_reg, None, 0, MemoryUpdate::PostIncrement, access));
 				}
 				ArgumentType::PreDecIndirectDestReg => {
-					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@-"))
+					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_prefix("@-"));
+					operands.push(memory(dest_reg, None, 0, MemoryUpdate::PreDecrement, access));
 				}
 				ArgumentType::PreDecIndirectSrcReg => {
-					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@-"))
+					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_prefix("@-"));
+					operands.push(memory(source_reg, None, 0, MemoryUpdate::PreDecrement, access));
 				}
 				ArgumentType::IndirectDestRegDisp => {
 					// Push displacement
-					tokens.push(Token::new(TokenBase::Immediate(displacement)).with_prefix("@(").with_suffix(""));
+					tokens.push(Token::new(TokenBase::Immediate(scaled)).with_prefix("@(").with_suffix(""));
 					// Push destination register
 					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_suffix(")"));
+					operands.push(memory(dest_reg, None, scaled, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectSrcRegDisp => {
 					// Push displacement
-					tokens.push(Token::new(TokenBase::Immediate(displacement)).with_prefix("@(").with_suffix(""));
+					tokens.push(Token::new(TokenBase::Immediate(scaled)).with_prefix("@(").with_suffix(""));
 					// Push source register
 					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_suffix(")"));
+					operands.push(memory(source_reg, None, scaled, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectIdxDestReg => {
 					// Push index register
 					tokens.push(Token::new(TokenBase::Register(Register::R0.static_str())).with_prefix("@(").with_suffix(""));
 					// Push destination register
 					tokens.push(Token::new(TokenBase::Register(dest_reg.static_str())).with_suffix(")"));
+					operands.push(memory(dest_reg, Some(Register::R0), 0, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectIdxSrcReg => {
 					// Push index register
 					tokens.push(Token::new(TokenBase::Register(Register::R0.static_str())).with_prefix("@(").with_suffix(""));
 					// Push source register
 					tokens.push(Token::new(TokenBase::Register(source_reg.static_str())).with_suffix(")"));
+					operands.push(memory(source_reg, Some(Register::R0), 0, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectGbrDisp => {
 					// Push displacement
-					tokens.push(Token::new(TokenBase::Immediate(displacement)).with_prefix("@(").with_suffix(""));
+					tokens.push(Token::new(TokenBase::Immediate(scaled)).with_prefix("@(").with_suffix(""));
 					// Push GBR
 					tokens.push(Token::new(TokenBase::Register(Register::GBR.static_str())).with_suffix(")"));
+					operands.push(memory(Register::GBR, None, scaled, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectIdxGbr => {
 					// Push R0
 					tokens.push(Token::new(TokenBase::Register(Register::R0.static_str())).with_prefix("@(").with_suffix(""));
 					// Push GBR
 					tokens.push(Token::new(TokenBase::Register(Register::GBR.static_str())).with_suffix(")"));
+					operands.push(memory(Register::GBR, Some(Register::R0), 0, MemoryUpdate::None, access));
 				}
 				ArgumentType::IndirectPcDisp => {
-					// Push displacement
-					tokens.push(Token::new(TokenBase::Immediate(displacement)).with_prefix("@(").with_suffix(""));
-					// Push PC
-					tokens.push(Token::new(TokenBase::Register(Register::PC.static_str())).with_suffix(")"));
+					let target = pc_relative(address, displacement, size);
+
+					// Load the constant from the literal pool. Words are sign-extended into the register
+					let value = match size {
+						2 => layout.read_i16(target).ok().map(|value| value as i32 as u32 as u64),
+						_ => layout.read_u32(target).ok().map(u64::from),
+					};
+					literal = Some(Literal {address: target, value});
+					tokens.push(Token::new(TokenBase::Address(target)).with_prefix("@(").with_suffix(")"));
+					operands.push(Operand::Memory(MemoryOperand {
+						base: None,
+						index: None,
+						displacement: target as isize,
+						size,
+						update: MemoryUpdate::None,
+						access,
+					}));
+				}
+				ArgumentType::PcRelativeAddress => {
+					let target = pc_relative(address, displacement, 4);
+					literal = Some(Literal {address: target, value: None});
+					tokens.push(Token::new(TokenBase::Address(target)).with_prefix("@(").with_suffix(")"));
+					operands.push(Operand::Target(target));
+				}
+				ArgumentType::BranchTarget => {
+					// Sign-extend the displacement, scale by the instruction size and add to PC + 4
+					let offset = match self.format {
+						SuperHFormat::D12(_) => (((displacement as u16) << 4) as i16 >> 4) as isize,
+						_ => displacement as u8 as i8 as isize,
+					};
+					let target = address.wrapping_add(4).wrapping_add((offset * 2) as usize);
+					branch_target = BranchTarget::Direct(target);
+					tokens.push(Token::new(TokenBase::Address(target)));
+					operands.push(Operand::Target(target));
 				}
-				_ => {},
 			}
 		}
 
-		Some(tokens)
+		Some(Instruction {tokens, operands, literal, info: self.info(branch_target)})
 	}
 }
 
 
+
+/// Calculates the address of a PC-relative operand. Longword accesses
+/// use PC with the lower two bits cleared
+fn pc_relative(address: usize, displacement: usize, size: usize) -> usize {
+	let pc = if size == 4 { address & !3 } else { address };
+	pc.wrapping_add(4).wrapping_add(displacement * size)
+}
+
+
+
+/// Amount of entries in the exception vector table
+const VECTOR_COUNT: usize = 256;
+
+
+
+/// Returns the name of an exception vector and whether it holds a handler address.
+/// Returns None for reserved vectors.
+fn vector_name(number: usize) -> Option<(String, bool)> {
+	let name = match number {
+		0 => "power_on_reset",
+		1 => return Some(("power_on_reset_sp".to_string(), false)),
+		2 => "manual_reset",
+		3 => return Some(("manual_reset_sp".to_string(), false)),
+		4 => "general_illegal_instruction",
+		6 => "slot_illegal_instruction",
+		9 => "cpu_address_error",
+		10 => "dmac_address_error",
+		11 => "nmi",
+		12 => "user_break",
+		13 => "fpu_exception",
+		14 => "hudi",
+		32..=63 => return Some((format!("trap_{}", number), true)),
+		64..=71 => return Some((format!("irq{}", number - 64), true)),
+		// Assignment of the on-chip peripheral vectors depends on the chip
+		72..=255 => return Some((format!("interrupt_{}", number), true)),
+		_ => return None,
+	};
+	Some((name.to_string(), true))
+}
+
+
+
+/// Returns true if the instruction word pushes a register onto the stack
+/// (`mov.l Rm,@-R15`, `sts.l MACH/MACL/PR,@-R15`)
+fn is_push(word: u16) -> bool {
+	(word & 0xFF0F == 0x2F06 && word != 0x2FF6) || word == 0x4F02 || word == 0x4F12 || word == 0x4F22
+}
+
+
+
+/// Returns true if the instruction word typically opens a function
+/// (`sts.l PR,@-R15` or `mov.l R14,@-R15`)
+fn is_prologue(word: u16) -> bool {
+	word == 0x4F22 || word == 0x2FE6
+}
+
+
+
 impl SH2E {
 	pub fn new() -> SH2E {
 		SH2E {}
 	}
 }
 
+impl Default for SH2E {
+	fn default() -> SH2E {
+		SH2E::new()
+	}
+}
+
 
 impl Architecture for SH2E {
 	fn disassemble_single(&self, layout: &Layout, address: usize) -> Result<(Instruction, usize)> {
-		let mut instr = [0; 2];
+		// Refuse to decode data sections
+		layout.check_access(address, 2, SectionFlags::Execute)?;
+
 		// Read two bytes for the instruction
-		let amount_read = layout.read_memory(address, &mut instr);
-		if amount_read < 2 {
-			return Err(Error::InvalidMemory);
-		}
+		let instr = layout.read_u16(address)?;
 
 		// Divide the instruction into nibbles
-		let nibbles = Nibbles {nibbles: [instr[0] >> 4, instr[0] & 0x0F, instr[1] >> 4, instr[1] & 0x0F]};
+		let nibbles = Nibbles {nibbles: [(instr >> 12) as u8, (instr >> 8) as u8 & 0x0F, (instr >> 4) as u8 & 0x0F, instr as u8 & 0x0F]};
 		
 		// Match the instruction
 		for i in INSTRUCTIONS {
-			if let Some(tokens) = i.disassemble(&nibbles) {
-				return Ok((Instruction {tokens}, 2));
+			if let Some(instruction) = i.disassemble(layout, &nibbles, address) {
+				return Ok((instruction, 2));
 			}
 		}
 
 		Err(Error::InvalidInstruction)
 	}
-}
\ No newline at end of file
+
+	fn lift(&self, instruction: &Instruction, address: usize) -> Vec<InstructionTree> {
+		lift::lift(instruction, address)
+	}
+
+	fn stack_pointer(&self) -> Option<RegisterId> {
+		Some(Register::R15.id())
+	}
+
+	fn callee_saved(&self) -> Vec<RegisterId> {
+		// Renesas and GCC calling conventions
+		[Register::R8, Register::R9, Register::R10, Register::R11, Register::R12, Register::R13, Register::R14, Register::R15,
+			Register::GBR, Register::VBR, Register::FR12, Register::FR13, Register::FR14, Register::FR15]
+			.iter().map(|register| register.id()).collect()
+	}
+
+	fn vector_table(&self, layout: &Layout, base: usize) -> Vec<Vector> {
+		let mut vectors = Vec::new();
+		for number in 0..VECTOR_COUNT {
+			let (name, handler) = match vector_name(number) {
+				Some(vector) => vector,
+				None => continue,
+			};
+
+			// The table ends with the address space
+			let address = match base.checked_add(number * 4) {
+				Some(address) if address as u64 <= 0xFFFF_FFFC => address,
+				_ => break,
+			};
+			let value = match layout.read_u32(address) {
+				Ok(value) => value as usize,
+				Err(_) => continue,
+			};
+
+			// Unused entries are often left erased (0xFFFFFFFF) or zero
+			if handler && (value % 2 != 0 || layout.check_access(value, 2, SectionFlags::Execute).is_err()) {
+				continue;
+			}
+
+			vectors.push(Vector {
+				address,
+				name,
+				value,
+				handler,
+			});
+		}
+		vectors
+	}
+
+	fn find_prologues(&self, layout: &Layout, disassembly: &Disassembly) -> Vec<usize> {
+		let mut starts = Vec::new();
+		for section in layout.sections().filter(|section| section.flags().contains(SectionFlags::Execute)) {
+			// Literals are neither prologues nor pushes
+			let word = |address: usize| if disassembly.is_literal(address) { 0 } else { layout.read_u16(address).unwrap_or(0) };
+
+			let mut address = (section.address() + 1) & !1;
+			while address + 2 <= section.end() {
+				if is_prologue(word(address)) {
+					// The function starts at the first of the consecutive pushes
+					let mut start = address;
+					while start >= section.address() + 2 && is_push(word(start - 2)) {
+						start -= 2;
+					}
+
+					// Skip the rest of the pushes
+					address += 2;
+					while address + 2 <= section.end() && is_push(word(address)) {
+						address += 2;
+					}
+					starts.push(start);
+				} else {
+					address += 2;
+				}
+			}
+		}
+		starts
+	}
+
+	fn register_move(&self, instruction: &Instruction) -> Option<(RegisterId, MoveSource)> {
+		let source = match (instruction.opcode(), instruction.operands()) {
+			// Immediates are sign-extended into the register
+			("mov", [Operand::Immediate(value), _]) => MoveSource::Constant(*value as u32 as u64),
+			("mov" | "ldc" | "lds" | "stc" | "sts", [Operand::Register(source), _]) => MoveSource::Register(*source),
+			// Literal pool loads
+			("mov.w" | "mov.l", [Operand::Memory(_), _]) => MoveSource::Constant(instruction.literal()?.value?),
+			("mova", [Operand::Target(target), _]) => MoveSource::Constant(*target as u64),
+			_ => return None,
+		};
+
+		match instruction.operands().last() {
+			Some(Operand::Register(destination)) => Some((*destination, source)),
+			_ => None,
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::architecture::Constants;
+	use crate::memory::{code_layout, Section};
+
+	/// Checks the listing of each word decoded on its own at 0x1000
+	fn check(cases: &[(u16, &str)]) {
+		let arch = SH2E::new();
+		for (word, expected) in cases {
+			let (instruction, size) = arch.disassemble_single(&code_layout(&[*word]), 0x1000).unwrap();
+			assert_eq!(instruction.to_string(), *expected, "{:04X}", word);
+			assert_eq!(size, 2);
+		}
+	}
+
+	#[test]
+	fn data_transfer() {
+		check(&[
+			(0x0009, "nop"), (0xE1FE, "mov #-2, R1"), (0x6123, "mov R2, R1"), (0x0129, "movt R1"),
+			(0x6120, "mov.b @R2, R1"), (0x6121, "mov.w @R2, R1"), (0x6122, "mov.l @R2, R1"),
+			(0x2120, "mov.b R2, @R1"), (0x2121, "mov.w R2, @R1"), (0x2122, "mov.l R2, @R1"),
+			(0x6124, "mov.b @R2+, R1"), (0x6125, "mov.w @R2+, R1"), (0x6126, "mov.l @R2+, R1"),
+			(0x2124, "mov.b R2, @-R1"), (0x2125, "mov.w R2, @-R1"), (0x2126, "mov.l R2, @-R1"),
+			(0x012C, "mov.b @(R0, R2), R1"), (0x012D, "mov.w @(R0, R2), R1"), (0x012E, "mov.l @(R0, R2), R1"),
+			(0x0124, "mov.b R2, @(R0, R1)"), (0x0125, "mov.w R2, @(R0, R1)"), (0x0126, "mov.l R2, @(R0, R1)"),
+			(0x8412, "mov.b @(2, R1), R0"), (0x8512, "mov.w @(4, R1), R0"), (0x5123, "mov.l @(12, R2), R1"),
+			(0x8012, "mov.b R0, @(2, R1)"), (0x8112, "mov.w R0, @(4, R1)"), (0x1123, "mov.l R2, @(12, R1)"),
+			(0xC402, "mov.b @(2, GBR), R0"), (0xC502, "mov.w @(4, GBR), R0"), (0xC602, "mov.l @(8, GBR), R0"),
+			(0xC002, "mov.b R0, @(2, GBR)"), (0xC102, "mov.w R0, @(4, GBR)"), (0xC202, "mov.l R0, @(8, GBR)"),
+			(0x6128, "swap.b R2, R1"), (0x6129, "swap.w R2, R1"), (0x212D, "xtrct R2, R1"),
+		]);
+	}
+
+	#[test]
+	fn arithmetic() {
+		check(&[
+			(0x312C, "add R2, R1"), (0x71FF, "add #-1, R1"), (0x312E, "addc R2, R1"), (0x312F, "addv R2, R1"),
+			(0x3128, "sub R2, R1"), (0x312A, "subc R2, R1"), (0x312B, "subv R2, R1"), (0x612B, "neg R2, R1"),
+			(0x612A, "negc R2, R1"), (0x4110, "dt R1"),
+			(0x8880, "cmp/eq #-128, R0"), (0x3120, "cmp/eq R2, R1"), (0x3122, "cmp/hs R2, R1"), (0x3123, "cmp/ge R2, R1"),
+			(0x3126, "cmp/hi R2, R1"), (0x3127, "cmp/gt R2, R1"), (0x4111, "cmp/pz R1"), (0x4115, "cmp/pl R1"),
+			(0x212C, "cmp/str R2, R1"),
+			(0x3124, "div1 R2, R1"), (0x2127, "div0s R2, R1"), (0x0019, "div0u"),
+			(0x0127, "mul.l R2, R1"), (0x212F, "muls.w R2, R1"), (0x212E, "mulu.w R2, R1"),
+			(0x312D, "dmuls.l R2, R1"), (0x3125, "dmulu.l R2, R1"), (0x012F, "mac.l @R2+, @R1+"), (0x412F, "mac.w @R2+, @R1+"),
+			(0x612E, "exts.b R2, R1"), (0x612F, "exts.w R2, R1"), (0x612C, "extu.b R2, R1"), (0x612D, "extu.w R2, R1"),
+		]);
+	}
+
+	#[test]
+	fn logic_and_shifts() {
+		check(&[
+			(0x2129, "and R2, R1"), (0xC9FF, "and #255, R0"), (0xCD01, "and.b #1, @(R0, GBR)"),
+			(0x212B, "or R2, R1"), (0xCB01, "or #1, R0"), (0xCF01, "or.b #1, @(R0, GBR)"),
+			(0x212A, "xor R2, R1"), (0xCA01, "xor #1, R0"), (0xCE01, "xor.b #1, @(R0, GBR)"),
+			(0x2128, "tst R2, R1"), (0xC801, "tst #1, R0"), (0xCC01, "tst.b #1, @(R0, GBR)"),
+			(0x6127, "not R2, R1"), (0x411B, "tas.b @R1"),
+			(0x4104, "rotl R1"), (0x4105, "rotr R1"), (0x4124, "rotcl R1"), (0x4125, "rotcr R1"),
+			(0x4120, "shal R1"), (0x4121, "shar R1"), (0x4100, "shll R1"), (0x4101, "shlr R1"),
+			(0x4108, "shll2 R1"), (0x4109, "shlr2 R1"), (0x4118, "shll8 R1"), (0x4119, "shlr8 R1"),
+			(0x4128, "shll16 R1"), (0x4129, "shlr16 R1"),
+		]);
+	}
+
+	#[test]
+	fn system_control() {
+		check(&[
+			(0x0008, "clrt"), (0x0018, "sett"), (0x0028, "clrmac"), (0x001B, "sleep"),
+			(0x410E, "ldc R1, SR"), (0x411E, "ldc R1, GBR"), (0x412E, "ldc R1, VBR"),
+			(0x4107, "ldc.l @R1+, SR"), (0x4117, "ldc.l @R1+, GBR"), (0x4127, "ldc.l @R1+, VBR"),
+			(0x410A, "lds R1, MACH"), (0x411A, "lds R1, MACL"), (0x412A, "lds R1, PR"),
+			(0x4106, "lds.l @R1+, MACH"), (0x4116, "lds.l @R1+, MACL"), (0x4126, "lds.l @R1+, PR"),
+			(0x0102, "stc SR, R1"), (0x0112, "stc GBR, R1"), (0x0122, "stc VBR, R1"),
+			(0x4103, "stc.l SR, @-R1"), (0x4113, "stc.l GBR, @-R1"), (0x4123, "stc.l VBR, @-R1"),
+			(0x010A, "sts MACH, R1"), (0x011A, "sts MACL, R1"), (0x012A, "sts PR, R1"),
+			(0x4102, "sts.l MACH, @-R1"), (0x4112, "sts.l MACL, @-R1"), (0x4F22, "sts.l PR, @-R15"),
+		]);
+	}
+
+	#[test]
+	fn invalid() {
+		let arch = SH2E::new();
+		for word in &[0xFFFF, 0x0000, 0x3121] {
+			assert!(matches!(arch.disassemble_single(&code_layout(&[*word]), 0x1000), Err(Error::InvalidInstruction)), "{:04X}", word);
+		}
+	}
+
+	#[test]
+	fn floating_point() {
+		check(&[
+			(0xF12C, "fmov FR2, FR1"), (0xF128, "fmov.s @R2, FR1"), (0xF12A, "fmov.s FR2, @R1"),
+			(0xF129, "fmov.s @R2+, FR1"), (0xF12B, "fmov.s FR2, @-R1"),
+			(0xF126, "fmov.s @(R0, R2), FR1"), (0xF127, "fmov.s FR2, @(R0, R1)"),
+			(0xF120, "fadd FR2, FR1"), (0xF121, "fsub FR2, FR1"), (0xF122, "fmul FR2, FR1"), (0xF123, "fdiv FR2, FR1"),
+			(0xF124, "fcmp/eq FR2, FR1"), (0xF125, "fcmp/gt FR2, FR1"), (0xF12E, "fmac FR0, FR2, FR1"),
+			(0xF14D, "fneg FR1"), (0xF15D, "fabs FR1"), (0xF18D, "fldi0 FR1"), (0xF19D, "fldi1 FR1"),
+			(0xFF2D, "float FPUL, FR15"), (0xF13D, "ftrc FR1, FPUL"), (0xF11D, "flds FR1, FPUL"), (0xF10D, "fsts FPUL, FR1"),
+			(0x415A, "lds R1, FPUL"), (0x416A, "lds R1, FPSCR"), (0x4156, "lds.l @R1+, FPUL"), (0x4166, "lds.l @R1+, FPSCR"),
+			(0x015A, "sts FPUL, R1"), (0x016A, "sts FPSCR, R1"), (0x4152, "sts.l FPUL, @-R1"), (0x4162, "sts.l FPSCR, @-R1"),
+		]);
+	}
+
+	#[test]
+	fn branch_targets() {
+		let words = [0xAFFE, 0xB001, 0x89FE, 0x8B00, 0x8D7F, 0x8F80, 0x0123, 0x0103, 0x412B, 0x410B, 0x000B, 0x002B, 0xC320, 0xA7FF, 0xA800];
+		let expected = [
+			"bra 0x1000", "bsr 0x1008", "bt 0x1004", "bf 0x100A", "bt/s 0x110A", "bf/s 0xF0E", "braf R1", "bsrf R1",
+			"jmp @R1", "jsr @R1", "rts", "rte", "trapa #32", "bra 0x201C", "bra 0x20",
+		];
+		let arch = SH2E::new();
+		let layout = code_layout(&words);
+		for (i, expected) in expected.iter().enumerate() {
+			let (instruction, _) = arch.disassemble_single(&layout, 0x1000 + i * 2).unwrap();
+			assert_eq!(instruction.to_string(), *expected);
+		}
+	}
+
+	#[test]
+	fn literal_pool() {
+		// The word and longword loads read 0x1008, the last load reads unmapped memory at 0x1010
+		let arch = SH2E::new();
+		let layout = code_layout(&[0x9102, 0xD101, 0xC700, 0xD202, 0x8000, 0x1234]);
+		let instruction = |address| arch.disassemble_single(&layout, address).unwrap().0;
+		assert_eq!(instruction(0x1000).to_string(), "mov.w @(0x1008), R1 ; =0xFFFF8000");
+		assert_eq!(instruction(0x1002).to_string(), "mov.l @(0x1008), R1 ; =0x80001234");
+		assert_eq!(instruction(0x1004).to_string(), "mova @(0x1008), R0");
+		assert_eq!(instruction(0x1006).to_string(), "mov.l @(0x1010), R2");
+		let load = instruction(0x1002);
+		let literal = load.literal().unwrap();
+		assert_eq!((literal.address, literal.value), (0x1008, Some(0x80001234)));
+		let load = instruction(0x1006);
+		let literal = load.literal().unwrap();
+		assert_eq!((literal.address, literal.value), (0x1010, None));
+	}
+
+	#[test]
+	fn instruction_info() {
+		// bra 0x1000 ; jmp @R1 ; rts ; bt 0x100A ; bt/s 0x100C ; nop
+		let arch = SH2E::new();
+		let layout = code_layout(&[0xAFFE, 0x412B, 0x000B, 0x8900, 0x8D00, 0x0009]);
+		let info = |address| *arch.disassemble_single(&layout, address).unwrap().0.info();
+		assert_eq!(info(0x1000).branch, BranchKind::Jump(BranchTarget::Direct(0x1000)));
+		assert_eq!(info(0x1000).delay_slots, 1);
+		assert_eq!(info(0x1002).branch, BranchKind::Jump(BranchTarget::Register(Register::R1.id())));
+		assert_eq!(info(0x1004).branch, BranchKind::Return);
+		assert_eq!(info(0x1006).delay_slots, 0);
+		assert_eq!(info(0x1008).delay_slots, 1);
+		assert!(info(0x1006).illegal_in_delay_slot);
+		assert!(!info(0x100A).illegal_in_delay_slot);
+		assert_eq!(info(0x100A).branch, BranchKind::None);
+	}
+
+	#[test]
+	fn typed_operands() {
+		// mov.l @(8, R2), R1 ; mov.w R0, @(4, R3) ; mov.l R1, @-R15 ; jsr @R3 ; mov.l @(12, GBR), R0
+		let arch = SH2E::new();
+		let layout = code_layout(&[0x5122, 0x8132, 0x2F16, 0x430B, 0xC603]);
+		let instruction = |address| arch.disassemble_single(&layout, address).unwrap().0;
+
+		let load = instruction(0x1000);
+		assert_eq!(load.operands()[1], Operand::Register(Register::R1.id()));
+		match load.operands()[0] {
+			Operand::Memory(memory) => {
+				assert_eq!((memory.base, memory.index), (Some(Register::R2.id()), None));
+				assert_eq!((memory.displacement, memory.size), (8, 4));
+				assert_eq!(memory.access, SectionFlags::Read);
+			}
+			ref operand => panic!("{:?}", operand),
+		}
+
+		match instruction(0x1002).operands()[1] {
+			Operand::Memory(memory) => assert_eq!((memory.size, memory.access), (2, SectionFlags::Write)),
+			ref operand => panic!("{:?}", operand),
+		}
+		match instruction(0x1004).operands()[1] {
+			Operand::Memory(memory) => assert_eq!((memory.base, memory.update), (Some(Register::R15.id()), MemoryUpdate::PreDecrement)),
+			ref operand => panic!("{:?}", operand),
+		}
+		assert_eq!(instruction(0x1006).operands(), &[Operand::Register(Register::R3.id())]);
+		match instruction(0x1008).operands()[0] {
+			Operand::Memory(memory) => assert_eq!((memory.base, memory.displacement), (Some(Register::GBR.id()), 12)),
+			ref operand => panic!("{:?}", operand),
+		}
+	}
+
+	#[test]
+	fn vector_table() {
+		// Reset vector, initial stack pointer, erased entry, zero stack pointer and misaligned handler
+		let layout = code_layout(&[0x0000, 0x1014, 0xFFFF, 0x8000, 0xFFFF, 0xFFFF, 0x0000, 0x0000, 0x0000, 0x1015, 0x000B, 0x0009]);
+		let vectors: Vec<(usize, String, usize, bool)> = SH2E::new().vector_table(&layout, 0x1000).into_iter()
+			.map(|vector| (vector.address, vector.name, vector.value, vector.handler))
+			.collect();
+		assert_eq!(vectors, vec![
+			(0x1000, "power_on_reset".to_string(), 0x1014, true),
+			(0x1004, "power_on_reset_sp".to_string(), 0xFFFF8000, false),
+			(0x100C, "manual_reset_sp".to_string(), 0, false),
+		]);
+	}
+
+	#[test]
+	fn vector_table_end() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0xFFFF_FFF8, vec![0xFF, 0xFF, 0xFF, 0xF8, 0, 0, 0, 0], SectionFlags::Read | SectionFlags::Execute)).unwrap();
+		let arch = SH2E::new();
+		let vectors: Vec<(usize, usize)> = arch.vector_table(&layout, 0xFFFF_FFF8).into_iter().map(|vector| (vector.address, vector.value)).collect();
+		assert_eq!(vectors, vec![(0xFFFF_FFF8, 0xFFFF_FFF8), (0xFFFF_FFFC, 0)]);
+		assert!(arch.vector_table(&layout, usize::MAX - 1).is_empty());
+	}
+
+	#[test]
+	fn prologues() {
+		// 1000: mov.w @(1006),R0; rts; nop; .word 4F22
+		// 1008: mov.l R8,@-R15; mov.l R14,@-R15; sts.l PR,@-R15; rts; nop
+		let layout = code_layout(&[0x9001, 0x000B, 0x0009, 0x4F22, 0x2F86, 0x2FE6, 0x4F22, 0x000B, 0x0009]);
+		let arch = SH2E::new();
+		let mut disassembly = Disassembly::new();
+		assert_eq!(arch.find_prologues(&layout, &disassembly), vec![0x1006]);
+
+		// The word loaded as a literal is not a prologue
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&arch, &layout);
+		assert!(disassembly.is_literal(0x1007) && !disassembly.is_literal(0x1008));
+		assert_eq!(arch.find_prologues(&layout, &disassembly), vec![0x1008]);
+	}
+
+	#[test]
+	fn register_moves() {
+		let arch = SH2E::new();
+		let register_move = |words: &[u16]| arch.register_move(&arch.disassemble_single(&code_layout(words), 0x1000).unwrap().0);
+		assert_eq!(register_move(&[0xE0FF]), Some((Register::R0.id(), MoveSource::Constant(0xFFFF_FFFF))));
+		assert_eq!(register_move(&[0x6013]), Some((Register::R0.id(), MoveSource::Register(Register::R1.id()))));
+		assert_eq!(register_move(&[0x411E]), Some((Register::GBR.id(), MoveSource::Register(Register::R1.id()))));
+		assert_eq!(register_move(&[0xC701]), Some((Register::R0.id(), MoveSource::Constant(0x1008))));
+		assert_eq!(register_move(&[0xD100, 0x0009, 0x1234, 0x5678]), Some((Register::R1.id(), MoveSource::Constant(0x1234_5678))));
+		assert_eq!(register_move(&[0x6012]), None);
+		assert_eq!(register_move(&[0x7001]), None);
+	}
+
+	#[test]
+	fn call_clobbers_constants() {
+		// mov #5,R2; jsr @R1; mov #6,R3
+		let layout = code_layout(&[0xE205, 0x410B, 0xE306]);
+		let arch = SH2E::new();
+		let instruction = |address| arch.disassemble_single(&layout, address).unwrap().0;
+		let mut constants = Constants::new();
+		constants.update(&arch, &instruction(0x1000));
+		assert_eq!(constants.get(Register::R2.id()), Some(5));
+
+		// The call clobbers the registers written by its delay slot too
+		constants.update(&arch, &instruction(0x1002));
+		assert_eq!(constants.get(Register::R2.id()), Some(5));
+		constants.update(&arch, &instruction(0x1004));
+		assert_eq!(constants.get(Register::R2.id()), None);
+		assert_eq!(constants.get(Register::R3.id()), None);
+	}
+}
@@ -0,0 +1,915 @@
+use crate::architecture::{Instruction, MemoryOperand, MemoryUpdate, Operand, RegisterId};
+use crate::architecture::sh2e::{Flag, Register};
+use crate::il::{self, Condition, InstructionId, InstructionTree, Size};
+use crate::il::Instruction as Il;
+
+use std::mem;
+
+
+
+/// Constructor of a binary IL operation
+type Binary = fn(Size, InstructionId, InstructionId) -> Il;
+
+
+
+/// Flags kept in SR and their bits
+const STATUS_FLAGS: [(Flag, u32); 3] = [(Flag::T, 0), (Flag::Q, 8), (Flag::M, 9)];
+
+
+
+/// Collects the statements of one instruction
+#[derive(Default)]
+struct Lifter {
+	statements: Vec<InstructionTree>,
+	tree: InstructionTree, // Statement being built
+}
+
+
+
+/// Returns true if the opcode only copies its source into its destination
+fn is_move(opcode: &str) -> bool {
+	matches!(opcode, "mov" | "mov.b" | "mov.w" | "mov.l" | "mova"
+		| "ldc" | "ldc.l" | "lds" | "lds.l" | "stc" | "stc.l" | "sts" | "sts.l"
+		| "fmov" | "fmov.s")
+}
+
+
+
+/// Returns the IL register with the size of its contents
+fn register(id: RegisterId) -> il::Register {
+	let float = id.id >= Register::FR0 as u16 && id.id <= Register::FR15 as u16;
+	il::Register::new(id, if float { Size::Float32 } else { Size::Int32 })
+}
+
+
+
+impl Lifter {
+	fn node(&mut self, instruction: Il) -> InstructionId {
+		self.tree.add_node(instruction)
+	}
+
+	/// Ends the current statement with its root
+	fn statement(&mut self, instruction: Il) {
+		self.tree.add_root(instruction);
+		self.statements.push(mem::take(&mut self.tree));
+	}
+
+	fn int(&mut self, value: u32) -> InstructionId {
+		self.node(Il::Constant(Size::Int32, value as u64))
+	}
+
+	/// Reads the register. The flag bits of SR are read from the flags
+	fn get(&mut self, register: il::Register) -> InstructionId {
+		if register.id != Register::SR.id() {
+			return self.node(Il::GetRegister(register));
+		}
+
+		let value = self.node(Il::GetRegister(register));
+		let mask = self.int(!STATUS_FLAGS.iter().fold(0, |mask, (_, bit)| mask | 1 << bit));
+		let mut result = self.node(Il::And(Size::Int32, value, mask));
+		for (flag, bit) in STATUS_FLAGS.iter() {
+			let value = self.get_flag(*flag);
+			let mut value = self.node(Il::ZeroExtend(Size::Int32, value));
+			if *bit != 0 {
+				let amount = self.int(*bit);
+				value = self.node(Il::ShiftLeft(Size::Int32, value, amount));
+			}
+			result = self.node(Il::Or(Size::Int32, result, value));
+		}
+		result
+	}
+
+	/// Writes the register. Writes to SR also set the flags from their bits
+	fn set(&mut self, register: il::Register, value: InstructionId) {
+		self.statement(Il::SetRegister(register, value));
+		if register.id != Register::SR.id() {
+			return;
+		}
+
+		for (flag, bit) in STATUS_FLAGS.iter() {
+			let mut value = self.node(Il::GetRegister(register));
+			if *bit != 0 {
+				let amount = self.int(*bit);
+				value = self.node(Il::LogicalShiftRight(Size::Int32, value, amount));
+			}
+			let one = self.int(1);
+			let masked = self.node(Il::And(Size::Int32, value, one));
+			let zero = self.int(0);
+			let set = self.node(Il::Compare(Condition::NotEqual, masked, zero));
+			self.set_flag(*flag, set);
+		}
+	}
+
+	fn get_flag(&mut self, flag: Flag) -> InstructionId {
+		self.node(Il::GetFlag(il::Flag(flag.id())))
+	}
+
+	fn set_flag(&mut self, flag: Flag, value: InstructionId) {
+		self.statement(Il::SetFlag(il::Flag(flag.id()), value));
+	}
+
+	/// Adds the nodes computing the effective address of the memory operand
+	fn address(&mut self, memory: &MemoryOperand) -> InstructionId {
+		let mut parts = Vec::new();
+		if let Some(base) = memory.base {
+			parts.push(self.get(register(base)));
+		}
+		if let Some(index) = memory.index {
+			parts.push(self.get(register(index)));
+		}
+		if memory.displacement != 0 || parts.is_empty() {
+			parts.push(self.int(memory.displacement as u32));
+		}
+
+		let mut result = parts[0];
+		for part in &parts[1..] {
+			result = self.node(Il::Add(Size::Int32, result, *part));
+		}
+		result
+	}
+
+	/// Adds the nodes reading a register, immediate or address operand
+	fn value(&mut self, operand: &Operand) -> InstructionId {
+		match operand {
+			Operand::Register(id) => self.get(register(*id)),
+			Operand::Immediate(value) => self.int(*value as u32),
+			Operand::Target(target) => self.int(*target as u32),
+			Operand::Memory(memory) => {
+				let address = self.address(memory);
+				self.node(Il::Load(Size::from_bytes(memory.size).unwrap_or(Size::Int32), address))
+			}
+		}
+	}
+
+	/// Adjusts the base register if the operand uses the update mode
+	fn update(&mut self, memory: &MemoryOperand, update: MemoryUpdate) {
+		let base = match memory.base {
+			Some(base) if memory.update == update => register(base),
+			_ => return,
+		};
+		let current = self.get(base);
+		let delta = self.int(memory.size as u32);
+		let result = self.node(match update {
+			MemoryUpdate::PreDecrement => Il::Sub(Size::Int32, current, delta),
+			_ => Il::Add(Size::Int32, current, delta),
+		});
+		self.set(base, result);
+	}
+
+	/// Lifts a copy. Long moves through R15 use the stack and narrow loads are sign-extended
+	fn lift_move(&mut self, source: &Operand, destination: &Operand) {
+		let stack = Some(Register::R15.id());
+		match (source, destination) {
+			(Operand::Register(source), Operand::Memory(memory))
+				if memory.base == stack && memory.update == MemoryUpdate::PreDecrement && memory.size == 4 => {
+				let source = register(*source);
+				let value = self.get(source);
+				return self.statement(Il::Push(source.size, value));
+			}
+			(Operand::Memory(memory), Operand::Register(destination))
+				if memory.base == stack && memory.update == MemoryUpdate::PostIncrement && memory.size == 4 => {
+				let destination = register(*destination);
+				let value = self.node(Il::Pop(destination.size));
+				return self.set(destination, value);
+			}
+			_ => {}
+		}
+
+		match (source, destination) {
+			(Operand::Memory(memory), Operand::Register(destination)) => {
+				let destination = register(*destination);
+				let size = if destination.size.is_float() { Size::Float32 } else { Size::from_bytes(memory.size).unwrap_or(Size::Int32) };
+				let address = self.address(memory);
+				let mut value = self.node(Il::Load(size, address));
+				if size.bits() < 32 {
+					value = self.node(Il::SignExtend(Size::Int32, value));
+				}
+				self.set(destination, value);
+				// The loaded value replaces the incremented base
+				if memory.base != Some(destination.id) {
+					self.update(memory, MemoryUpdate::PostIncrement);
+				}
+			}
+			(_, Operand::Register(destination)) => {
+				let value = self.value(source);
+				self.set(register(*destination), value);
+			}
+			(Operand::Register(source), Operand::Memory(memory)) => {
+				self.update(memory, MemoryUpdate::PreDecrement);
+				let source = register(*source);
+				let size = if source.size.is_float() { Size::Float32 } else { Size::from_bytes(memory.size).unwrap_or(Size::Int32) };
+				let mut value = self.get(source);
+				if size.bits() < 32 {
+					value = self.node(Il::Truncate(size, value));
+				}
+				let address = self.address(memory);
+				self.statement(Il::Store(size, address, value));
+			}
+			_ => self.statement(Il::Unimplemented),
+		}
+	}
+
+	/// Lifts `destination = destination <op> source` at the size of the destination
+	fn arithmetic(&mut self, op: Binary, source: &Operand, destination: RegisterId) {
+		let destination = register(destination);
+		let left = self.get(destination);
+		let right = self.value(source);
+		let result = self.node(op(destination.size, left, right));
+		self.set(destination, result);
+	}
+
+	/// Lifts `destination = <op>(source)`
+	fn unary(&mut self, op: fn(Size, InstructionId) -> Il, source: RegisterId, destination: RegisterId) {
+		let destination = register(destination);
+		let value = self.get(register(source));
+		let result = self.node(op(destination.size, value));
+		self.set(destination, result);
+	}
+
+	/// Lifts a shift of the register by a constant amount
+	fn shift(&mut self, op: Binary, amount: u32, destination: RegisterId) {
+		self.arithmetic(op, &Operand::Immediate(amount as i64), destination);
+	}
+
+	/// Sets T to the condition between two values
+	fn compare(&mut self, condition: Condition, left: InstructionId, right: InstructionId) {
+		let result = self.node(Il::Compare(condition, left, right));
+		self.set_flag(Flag::T, result);
+	}
+
+	/// Sets T to a bit of the register
+	fn test_bit(&mut self, source: RegisterId, bit: u32) {
+		let value = self.get(register(source));
+		let bit = self.int(bit);
+		let shifted = self.node(Il::LogicalShiftRight(Size::Int32, value, bit));
+		let one = self.int(1);
+		let masked = self.node(Il::And(Size::Int32, shifted, one));
+		let zero = self.int(0);
+		self.compare(Condition::NotEqual, masked, zero);
+	}
+
+	/// Lifts an operation with carry or borrow through T. The operation is computed
+	/// in 64 bits and T is set if any bit above the low 32 is set. A missing left side is zero.
+	fn with_carry(&mut self, op: Binary, left: Option<RegisterId>, right: RegisterId, destination: RegisterId) {
+		let temporary = il::Register::temporary(0, Size::Int64);
+		let left = match left {
+			Some(left) => {
+				let value = self.get(register(left));
+				self.node(Il::ZeroExtend(Size::Int64, value))
+			}
+			None => self.node(Il::Constant(Size::Int64, 0)),
+		};
+		let right = self.get(register(right));
+		let right = self.node(Il::ZeroExtend(Size::Int64, right));
+		let result = self.node(op(Size::Int64, left, right));
+		let carry = self.get_flag(Flag::T);
+		let carry = self.node(Il::ZeroExtend(Size::Int64, carry));
+		let result = self.node(op(Size::Int64, result, carry));
+		self.set(temporary, result);
+
+		let result = self.get(temporary);
+		let low = self.node(Il::Truncate(Size::Int32, result));
+		self.set(register(destination), low);
+
+		let result = self.get(temporary);
+		let amount = self.int(32);
+		let high = self.node(Il::LogicalShiftRight(Size::Int64, result, amount));
+		let zero = self.node(Il::Constant(Size::Int64, 0));
+		self.compare(Condition::NotEqual, high, zero);
+	}
+
+	/// Lifts a signed operation setting T on overflow. The operation is computed
+	/// in 64 bits and overflows if the result does not fit in 32.
+	fn with_overflow(&mut self, op: Binary, source: RegisterId, destination: RegisterId) {
+		let temporary = il::Register::temporary(0, Size::Int64);
+		let left = self.get(register(destination));
+		let left = self.node(Il::SignExtend(Size::Int64, left));
+		let right = self.get(register(source));
+		let right = self.node(Il::SignExtend(Size::Int64, right));
+		let result = self.node(op(Size::Int64, left, right));
+		self.set(temporary, result);
+
+		let result = self.get(temporary);
+		let low = self.node(Il::Truncate(Size::Int32, result));
+		self.set(register(destination), low);
+
+		let result = self.get(temporary);
+		let low = self.get(temporary);
+		let low = self.node(Il::Truncate(Size::Int32, low));
+		let extended = self.node(Il::SignExtend(Size::Int64, low));
+		self.compare(Condition::NotEqual, result, extended);
+	}
+
+	/// Sets `MACL` to the product of the low words of both registers
+	fn multiply_word(&mut self, extend: fn(Size, InstructionId) -> Il, source: RegisterId, destination: RegisterId) {
+		let left = self.get(register(destination));
+		let left = self.node(Il::Truncate(Size::Int16, left));
+		let left = self.node(extend(Size::Int32, left));
+		let right = self.get(register(source));
+		let right = self.node(Il::Truncate(Size::Int16, right));
+		let right = self.node(extend(Size::Int32, right));
+		let product = self.node(Il::Mul(Size::Int32, left, right));
+		self.set(register(Register::MACL.id()), product);
+	}
+
+	/// Sets `MACH:MACL` to the 64-bit product of both registers
+	fn multiply_long(&mut self, extend: fn(Size, InstructionId) -> Il, source: RegisterId, destination: RegisterId) {
+		let temporary = il::Register::temporary(0, Size::Int64);
+		let left = self.get(register(destination));
+		let left = self.node(extend(Size::Int64, left));
+		let right = self.get(register(source));
+		let right = self.node(extend(Size::Int64, right));
+		let product = self.node(Il::Mul(Size::Int64, left, right));
+		self.set(temporary, product);
+
+		let product = self.get(temporary);
+		let amount = self.int(32);
+		let high = self.node(Il::LogicalShiftRight(Size::Int64, product, amount));
+		let high = self.node(Il::Truncate(Size::Int32, high));
+		self.set(register(Register::MACH.id()), high);
+
+		let product = self.get(temporary);
+		let low = self.node(Il::Truncate(Size::Int32, product));
+		self.set(register(Register::MACL.id()), low);
+	}
+
+	/// Lifts a read-modify-write of the byte with an immediate
+	fn modify_byte(&mut self, op: Binary, immediate: i64, memory: &MemoryOperand) {
+		let address = self.address(memory);
+		let value = self.node(Il::Load(Size::Int8, address));
+		let immediate = self.node(Il::Constant(Size::Int8, immediate as u8 as u64));
+		let result = self.node(op(Size::Int8, value, immediate));
+		let address = self.address(memory);
+		self.statement(Il::Store(Size::Int8, address, result));
+	}
+
+	/// Lifts a rotate through T. The old T enters at the opposite end
+	fn rotate_with_carry(&mut self, left: bool, destination: RegisterId) {
+		let temporary = il::Register::temporary(0, Size::Bool);
+		let carry = self.get_flag(Flag::T);
+		self.set(temporary, carry);
+		self.test_bit(destination, if left { 31 } else { 0 });
+
+		let destination = register(destination);
+		let value = self.get(destination);
+		let one = self.int(1);
+		let shifted = self.node(if left {
+			Il::ShiftLeft(Size::Int32, value, one)
+		} else {
+			Il::LogicalShiftRight(Size::Int32, value, one)
+		});
+		let carry = self.get(temporary);
+		let mut carry = self.node(Il::ZeroExtend(Size::Int32, carry));
+		if !left {
+			let amount = self.int(31);
+			carry = self.node(Il::ShiftLeft(Size::Int32, carry, amount));
+		}
+		let result = self.node(Il::Or(Size::Int32, shifted, carry));
+		self.set(destination, result);
+	}
+
+	/// Lifts one step of a non-restoring division of Rn by Rm. Rn is shifted left through T,
+	/// then Rm is subtracted if Q equals M and added otherwise. Q receives the old top bit
+	/// of Rn combined with the carry and M, and T is set if Q equals M.
+	fn divide_step(&mut self, divisor: RegisterId, dividend: RegisterId) {
+		let q = il::Register::temporary(0, Size::Bool);
+		let mask = il::Register::temporary(1, Size::Int64);
+		let result = il::Register::temporary(2, Size::Int64);
+		let (divisor, dividend) = (register(divisor), register(dividend));
+
+		let value = self.get_flag(Flag::Q);
+		self.set(q, value);
+		let value = self.get(dividend);
+		let zero = self.int(0);
+		let negative = self.node(Il::Compare(Condition::SignedLess, value, zero));
+		self.set_flag(Flag::Q, negative);
+
+		let value = self.get(dividend);
+		let one = self.int(1);
+		let shifted = self.node(Il::ShiftLeft(Size::Int32, value, one));
+		let t = self.get_flag(Flag::T);
+		let t = self.node(Il::ZeroExtend(Size::Int32, t));
+		let value = self.node(Il::Or(Size::Int32, shifted, t));
+		self.set(dividend, value);
+
+		// The divisor is negated when subtracting, selected by a mask of all ones
+		let old_q = self.get(q);
+		let m = self.get_flag(Flag::M);
+		let subtract = self.node(Il::Compare(Condition::Equal, old_q, m));
+		let subtract = self.node(Il::ZeroExtend(Size::Int64, subtract));
+		let value = self.node(Il::Neg(Size::Int64, subtract));
+		self.set(mask, value);
+
+		let value = self.get(divisor);
+		let value = self.node(Il::ZeroExtend(Size::Int64, value));
+		let negated = self.node(Il::Neg(Size::Int64, value));
+		let selected = self.get(mask);
+		let negated = self.node(Il::And(Size::Int64, negated, selected));
+		let value = self.get(divisor);
+		let value = self.node(Il::ZeroExtend(Size::Int64, value));
+		let selected = self.get(mask);
+		let inverted = self.node(Il::Not(Size::Int64, selected));
+		let value = self.node(Il::And(Size::Int64, value, inverted));
+		let operand = self.node(Il::Or(Size::Int64, negated, value));
+		let value = self.get(dividend);
+		let value = self.node(Il::ZeroExtend(Size::Int64, value));
+		let value = self.node(Il::Add(Size::Int64, value, operand));
+		self.set(result, value);
+
+		let value = self.get(result);
+		let low = self.node(Il::Truncate(Size::Int32, value));
+		self.set(dividend, low);
+
+		// Carries and borrows leave bits above the low 32
+		let value = self.get(result);
+		let amount = self.int(32);
+		let high = self.node(Il::LogicalShiftRight(Size::Int64, value, amount));
+		let zero = self.node(Il::Constant(Size::Int64, 0));
+		let carry = self.node(Il::Compare(Condition::NotEqual, high, zero));
+		let value = self.get_flag(Flag::Q);
+		let value = self.node(Il::Xor(Size::Bool, value, carry));
+		let m = self.get_flag(Flag::M);
+		let value = self.node(Il::Xor(Size::Bool, value, m));
+		self.set_flag(Flag::Q, value);
+
+		let q = self.get_flag(Flag::Q);
+		let m = self.get_flag(Flag::M);
+		self.compare(Condition::Equal, q, m);
+	}
+
+	/// Sets PR to the return address of a call
+	fn link(&mut self, return_address: u32) {
+		let value = self.int(return_address);
+		self.set(register(Register::PR.id()), value);
+	}
+}
+
+
+
+/// Translates the instruction into IL statements. Delay slots are lifted separately,
+/// so the statements of a delayed branch only describe the branch itself.
+pub(super) fn lift(instruction: &Instruction, address: usize) -> Vec<InstructionTree> {
+	use Operand::{Immediate, Memory, Register as Reg, Target};
+
+	// PC-relative branches and return addresses are relative to the instruction after the delay slot
+	let next = address.wrapping_add(4) as u32;
+	let mut l = Lifter::default();
+
+	match (instruction.opcode(), instruction.operands()) {
+		("nop", []) => {}
+
+		// Data transfer
+		(opcode, [source, destination]) if is_move(opcode) => l.lift_move(source, destination),
+		("movt", [Reg(n)]) => {
+			let t = l.get_flag(Flag::T);
+			let value = l.node(Il::ZeroExtend(Size::Int32, t));
+			l.set(register(*n), value);
+		}
+		("swap.b", [Reg(m), Reg(n)]) => {
+			// The two low bytes are exchanged
+			let value = l.get(register(*m));
+			let mask = l.int(0xFFFF_0000);
+			let high = l.node(Il::And(Size::Int32, value, mask));
+			let value = l.get(register(*m));
+			let mask = l.int(0xFF);
+			let low = l.node(Il::And(Size::Int32, value, mask));
+			let amount = l.int(8);
+			let low = l.node(Il::ShiftLeft(Size::Int32, low, amount));
+			let value = l.get(register(*m));
+			let amount = l.int(8);
+			let middle = l.node(Il::LogicalShiftRight(Size::Int32, value, amount));
+			let mask = l.int(0xFF);
+			let middle = l.node(Il::And(Size::Int32, middle, mask));
+			let result = l.node(Il::Or(Size::Int32, high, low));
+			let result = l.node(Il::Or(Size::Int32, result, middle));
+			l.set(register(*n), result);
+		}
+		("swap.w", [Reg(m), Reg(n)]) => {
+			let value = l.get(register(*m));
+			let amount = l.int(16);
+			let result = l.node(Il::RotateLeft(Size::Int32, value, amount));
+			l.set(register(*n), result);
+		}
+		("xtrct", [Reg(m), Reg(n)]) => {
+			let low = l.get(register(*n));
+			let amount = l.int(16);
+			let low = l.node(Il::LogicalShiftRight(Size::Int32, low, amount));
+			let high = l.get(register(*m));
+			let amount = l.int(16);
+			let high = l.node(Il::ShiftLeft(Size::Int32, high, amount));
+			let result = l.node(Il::Or(Size::Int32, high, low));
+			l.set(register(*n), result);
+		}
+
+		// Arithmetic
+		("add", [source, Reg(n)]) => l.arithmetic(Il::Add, source, *n),
+		("sub", [source, Reg(n)]) => l.arithmetic(Il::Sub, source, *n),
+		("addc", [Reg(m), Reg(n)]) => l.with_carry(Il::Add, Some(*n), *m, *n),
+		("subc", [Reg(m), Reg(n)]) => l.with_carry(Il::Sub, Some(*n), *m, *n),
+		("negc", [Reg(m), Reg(n)]) => l.with_carry(Il::Sub, None, *m, *n),
+		("addv", [Reg(m), Reg(n)]) => l.with_overflow(Il::Add, *m, *n),
+		("subv", [Reg(m), Reg(n)]) => l.with_overflow(Il::Sub, *m, *n),
+		("neg", [Reg(m), Reg(n)]) => l.unary(Il::Neg, *m, *n),
+		("dt", [Reg(n)]) => {
+			l.arithmetic(Il::Sub, &Immediate(1), *n);
+			let value = l.get(register(*n));
+			let zero = l.int(0);
+			l.compare(Condition::Equal, value, zero);
+		}
+
+		// Comparisons set T
+		("cmp/eq" | "cmp/hs" | "cmp/ge" | "cmp/hi" | "cmp/gt", [source, Reg(n)]) => {
+			let condition = match instruction.opcode() {
+				"cmp/eq" => Condition::Equal,
+				"cmp/hs" => Condition::UnsignedLessEqual,
+				"cmp/ge" => Condition::SignedLessEqual,
+				"cmp/hi" => Condition::UnsignedLess,
+				_ => Condition::SignedLess,
+			};
+			// Rn is compared against the source: `Rm <= Rn` for cmp/hs
+			let left = l.value(source);
+			let right = l.get(register(*n));
+			l.compare(condition, left, right);
+		}
+		("cmp/pz" | "cmp/pl", [Reg(n)]) => {
+			let condition = if instruction.opcode() == "cmp/pz" { Condition::SignedLessEqual } else { Condition::SignedLess };
+			let zero = l.int(0);
+			let value = l.get(register(*n));
+			l.compare(condition, zero, value);
+		}
+		("cmp/str", [Reg(m), Reg(n)]) => {
+			// T is set if any byte of both registers is equal
+			let temporary = il::Register::temporary(0, Size::Int32);
+			let left = l.get(register(*m));
+			let right = l.get(register(*n));
+			let difference = l.node(Il::Xor(Size::Int32, left, right));
+			l.set(temporary, difference);
+
+			let mut result = None;
+			for byte in 0..4 {
+				let value = l.get(temporary);
+				let amount = l.int(byte * 8);
+				let value = l.node(Il::LogicalShiftRight(Size::Int32, value, amount));
+				let value = l.node(Il::Truncate(Size::Int8, value));
+				let zero = l.node(Il::Constant(Size::Int8, 0));
+				let equal = l.node(Il::Compare(Condition::Equal, value, zero));
+				result = Some(match result {
+					Some(result) => l.node(Il::Or(Size::Bool, result, equal)),
+					None => equal,
+				});
+			}
+			l.set_flag(Flag::T, result.unwrap());
+		}
+
+		// Division steps
+		("div0s", [Reg(m), Reg(n)]) => {
+			for (flag, source) in [(Flag::Q, *n), (Flag::M, *m)].iter() {
+				let value = l.get(register(*source));
+				let zero = l.int(0);
+				let negative = l.node(Il::Compare(Condition::SignedLess, value, zero));
+				l.set_flag(*flag, negative);
+			}
+			let q = l.get_flag(Flag::Q);
+			let m = l.get_flag(Flag::M);
+			l.compare(Condition::NotEqual, q, m);
+		}
+		("div1", [Reg(m), Reg(n)]) => l.divide_step(*m, *n),
+		("div0u", []) => {
+			for flag in [Flag::Q, Flag::M, Flag::T].iter() {
+				let zero = l.node(Il::Constant(Size::Bool, 0));
+				l.set_flag(*flag, zero);
+			}
+		}
+
+		// Multiplication
+		("mul.l", [Reg(m), Reg(n)]) => {
+			let left = l.get(register(*n));
+			let right = l.get(register(*m));
+			let product = l.node(Il::Mul(Size::Int32, left, right));
+			l.set(register(Register::MACL.id()), product);
+		}
+		("muls.w", [Reg(m), Reg(n)]) => l.multiply_word(Il::SignExtend, *m, *n),
+		("mulu.w", [Reg(m), Reg(n)]) => l.multiply_word(Il::ZeroExtend, *m, *n),
+		("dmuls.l", [Reg(m), Reg(n)]) => l.multiply_long(Il::SignExtend, *m, *n),
+		("dmulu.l", [Reg(m), Reg(n)]) => l.multiply_long(Il::ZeroExtend, *m, *n),
+		("mac.l" | "mac.w", [Memory(m), Memory(n)]) => {
+			// The saturating accumulation is not expressed, but the address registers are still updated
+			l.statement(Il::Unimplemented);
+			l.update(m, MemoryUpdate::PostIncrement);
+			l.update(n, MemoryUpdate::PostIncrement);
+		}
+		("clrmac", []) => {
+			for id in [Register::MACH, Register::MACL].iter() {
+				let zero = l.int(0);
+				l.set(register(id.id()), zero);
+			}
+		}
+
+		// Extension
+		("exts.b" | "exts.w" | "extu.b" | "extu.w", [Reg(m), Reg(n)]) => {
+			let opcode = instruction.opcode();
+			let size = if opcode.ends_with(".b") { Size::Int8 } else { Size::Int16 };
+			let value = l.get(register(*m));
+			let value = l.node(Il::Truncate(size, value));
+			let value = l.node(if opcode.starts_with("exts") {
+				Il::SignExtend(Size::Int32, value)
+			} else {
+				Il::ZeroExtend(Size::Int32, value)
+			});
+			l.set(register(*n), value);
+		}
+
+		// Logic
+		("and", [source, Reg(n)]) => l.arithmetic(Il::And, source, *n),
+		("or", [source, Reg(n)]) => l.arithmetic(Il::Or, source, *n),
+		("xor", [source, Reg(n)]) => l.arithmetic(Il::Xor, source, *n),
+		("and.b", [Immediate(i), Memory(memory)]) => l.modify_byte(Il::And, *i, memory),
+		("or.b", [Immediate(i), Memory(memory)]) => l.modify_byte(Il::Or, *i, memory),
+		("xor.b", [Immediate(i), Memory(memory)]) => l.modify_byte(Il::Xor, *i, memory),
+		("not", [Reg(m), Reg(n)]) => l.unary(Il::Not, *m, *n),
+		("tst", [source, Reg(n)]) => {
+			let left = l.get(register(*n));
+			let right = l.value(source);
+			let masked = l.node(Il::And(Size::Int32, left, right));
+			let zero = l.int(0);
+			l.compare(Condition::Equal, masked, zero);
+		}
+		("tst.b", [Immediate(i), Memory(memory)]) => {
+			let address = l.address(memory);
+			let value = l.node(Il::Load(Size::Int8, address));
+			let mask = l.node(Il::Constant(Size::Int8, *i as u8 as u64));
+			let masked = l.node(Il::And(Size::Int8, value, mask));
+			let zero = l.node(Il::Constant(Size::Int8, 0));
+			l.compare(Condition::Equal, masked, zero);
+		}
+		("tas.b", [Memory(memory)]) => {
+			// Tests the byte for zero and sets its top bit
+			let temporary = il::Register::temporary(0, Size::Int8);
+			let address = l.address(memory);
+			let value = l.node(Il::Load(Size::Int8, address));
+			l.set(temporary, value);
+
+			let value = l.get(temporary);
+			let zero = l.node(Il::Constant(Size::Int8, 0));
+			l.compare(Condition::Equal, value, zero);
+
+			let value = l.get(temporary);
+			let bit = l.node(Il::Constant(Size::Int8, 0x80));
+			let value = l.node(Il::Or(Size::Int8, value, bit));
+			let address = l.address(memory);
+			l.statement(Il::Store(Size::Int8, address, value));
+		}
+
+		// Shifts and rotates. Single-bit shifts move the bit shifted out into T
+		("rotl", [Reg(n)]) => {
+			l.test_bit(*n, 31);
+			l.shift(Il::RotateLeft, 1, *n);
+		}
+		("rotr", [Reg(n)]) => {
+			l.test_bit(*n, 0);
+			l.shift(Il::RotateRight, 1, *n);
+		}
+		("rotcl", [Reg(n)]) => l.rotate_with_carry(true, *n),
+		("rotcr", [Reg(n)]) => l.rotate_with_carry(false, *n),
+		("shal" | "shll", [Reg(n)]) => {
+			l.test_bit(*n, 31);
+			l.shift(Il::ShiftLeft, 1, *n);
+		}
+		("shar", [Reg(n)]) => {
+			l.test_bit(*n, 0);
+			l.shift(Il::ArithmeticShiftRight, 1, *n);
+		}
+		("shlr", [Reg(n)]) => {
+			l.test_bit(*n, 0);
+			l.shift(Il::LogicalShiftRight, 1, *n);
+		}
+		("shll2", [Reg(n)]) => l.shift(Il::ShiftLeft, 2, *n),
+		("shll8", [Reg(n)]) => l.shift(Il::ShiftLeft, 8, *n),
+		("shll16", [Reg(n)]) => l.shift(Il::ShiftLeft, 16, *n),
+		("shlr2", [Reg(n)]) => l.shift(Il::LogicalShiftRight, 2, *n),
+		("shlr8", [Reg(n)]) => l.shift(Il::LogicalShiftRight, 8, *n),
+		("shlr16", [Reg(n)]) => l.shift(Il::LogicalShiftRight, 16, *n),
+
+		// System control
+		("clrt" | "sett", []) => {
+			let value = l.node(Il::Constant(Size::Bool, (instruction.opcode() == "sett") as u64));
+			l.set_flag(Flag::T, value);
+		}
+
+		// Branches
+		("bra", [Target(target)]) => {
+			let target = l.int(*target as u32);
+			l.statement(Il::Jump(target));
+		}
+		("bsr", [Target(target)]) => {
+			l.link(next);
+			let target = l.int(*target as u32);
+			l.statement(Il::Call(target));
+		}
+		("bt" | "bt/s" | "bf" | "bf/s", [Target(target)]) => {
+			let mut condition = l.get_flag(Flag::T);
+			if instruction.opcode().starts_with("bf") {
+				condition = l.node(Il::Not(Size::Bool, condition));
+			}
+			let target = l.int(*target as u32);
+			l.statement(Il::If(condition, target));
+		}
+		("braf" | "bsrf", [Reg(m)]) => {
+			let call = instruction.opcode() == "bsrf";
+			if call {
+				l.link(next);
+			}
+			let base = l.int(next);
+			let offset = l.get(register(*m));
+			let target = l.node(Il::Add(Size::Int32, base, offset));
+			l.statement(if call { Il::Call(target) } else { Il::Jump(target) });
+		}
+		("jmp", [Reg(m)]) => {
+			let target = l.get(register(*m));
+			l.statement(Il::Jump(target));
+		}
+		("jsr", [Reg(m)]) => {
+			l.link(next);
+			let target = l.get(register(*m));
+			l.statement(Il::Call(target));
+		}
+		("rts", []) => {
+			let target = l.get(register(Register::PR.id()));
+			l.statement(Il::Return(target));
+		}
+		("rte", []) => {
+			// PC and SR are restored from the stack
+			let temporary = il::Register::temporary(0, Size::Int32);
+			let pc = l.node(Il::Pop(Size::Int32));
+			l.set(temporary, pc);
+			let sr = l.node(Il::Pop(Size::Int32));
+			l.set(register(Register::SR.id()), sr);
+			let target = l.get(temporary);
+			l.statement(Il::Return(target));
+		}
+		("trapa", [Immediate(number)]) => l.statement(Il::Trap(*number as u32)),
+
+		// Floating point unit
+		("fadd", [source, Reg(n)]) => l.arithmetic(Il::Add, source, *n),
+		("fsub", [source, Reg(n)]) => l.arithmetic(Il::Sub, source, *n),
+		("fmul", [source, Reg(n)]) => l.arithmetic(Il::Mul, source, *n),
+		("fdiv", [source, Reg(n)]) => l.arithmetic(Il::Div, source, *n),
+		("fcmp/eq" | "fcmp/gt", [Reg(m), Reg(n)]) => {
+			// fcmp/gt tests FRn > FRm
+			let condition = if instruction.opcode() == "fcmp/eq" { Condition::Equal } else { Condition::SignedLess };
+			let left = l.get(register(*m));
+			let right = l.get(register(*n));
+			l.compare(condition, left, right);
+		}
+		("fmac", [Reg(zero), Reg(m), Reg(n)]) => {
+			let left = l.get(register(*zero));
+			let right = l.get(register(*m));
+			let product = l.node(Il::Mul(Size::Float32, left, right));
+			let accumulator = l.get(register(*n));
+			let result = l.node(Il::Add(Size::Float32, product, accumulator));
+			l.set(register(*n), result);
+		}
+		("fneg", [Reg(n)]) => l.unary(Il::Neg, *n, *n),
+		("fabs", [Reg(n)]) => l.unary(Il::Abs, *n, *n),
+		("fldi0" | "fldi1", [Reg(n)]) => {
+			let bits = if instruction.opcode() == "fldi1" { 1f32.to_bits() } else { 0 };
+			let value = l.node(Il::Constant(Size::Float32, bits as u64));
+			l.set(register(*n), value);
+		}
+		("float", [Reg(m), Reg(n)]) => l.unary(Il::IntToFloat, *m, *n),
+		("ftrc", [Reg(m), Reg(n)]) => l.unary(Il::FloatToInt, *m, *n),
+		("flds" | "fsts", [Reg(m), Reg(n)]) => l.unary(Il::BitCast, *m, *n),
+
+		// sleep, and anything with unexpected operands
+		_ => l.statement(Il::Unimplemented),
+	}
+	l.statements
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use crate::architecture::Architecture;
+	use crate::architecture::sh2e::SH2E;
+	use crate::dataflow::{Dataflow, Value};
+	use crate::memory::code_layout;
+	use crate::workspace::code_workspace;
+
+	/// Lifts the first instruction of big-endian SH-2 words at 0x1000 and renders the statements
+	fn lift(words: &[u16]) -> Vec<String> {
+		let arch = SH2E::new();
+		let (instruction, _) = arch.disassemble_single(&code_layout(words), 0x1000).unwrap();
+		let trees = arch.lift(&instruction, 0x1000);
+		for tree in &trees {
+			tree.validate().unwrap();
+		}
+		trees.iter().map(|tree| tree.to_string()).collect()
+	}
+
+	#[test]
+	fn moves() {
+		assert_eq!(lift(&[0x6013]), ["R0 = R1"]);
+		assert_eq!(lift(&[0xE0FF]), ["R0 = 0xFFFFFFFF"]);
+		assert_eq!(lift(&[0x6016]), ["R0 = [R1].d", "R1 = R1 + 4"]);
+		assert_eq!(lift(&[0x2124]), ["R1 = R1 - 1", "[R1].b = low.b(R2)"]);
+		assert_eq!(lift(&[0x8111]), ["[R1 + 2].w = low.w(R0)"]);
+		assert_eq!(lift(&[0xC201]), ["[GBR + 4].d = R0"]);
+		assert_eq!(lift(&[0xD100, 0x0009, 0x1234, 0x5678]), ["R1 = [0x1004].d"]);
+		assert_eq!(lift(&[0x9001, 0x0009, 0x8000]), ["R0 = sx.d([0x1006].w)"]);
+		assert_eq!(lift(&[0x401E]), ["GBR = R0"]);
+	}
+
+	#[test]
+	fn stack() {
+		assert_eq!(lift(&[0x2F86]), ["push.d(R8)"]);
+		assert_eq!(lift(&[0x4F22]), ["push.d(PR)"]);
+		assert_eq!(lift(&[0x6EF6]), ["R14 = pop.d"]);
+		assert_eq!(lift(&[0x4F26]), ["PR = pop.d"]);
+		// Only R15 is the stack pointer
+		assert_eq!(lift(&[0x6F36]), ["R15 = [R3].d", "R3 = R3 + 4"]);
+		assert_eq!(lift(&[0x2F85]), ["R15 = R15 - 2", "[R15].w = low.w(R8)"]);
+	}
+
+	#[test]
+	fn add() {
+		assert_eq!(lift(&[0x7001]), ["R0 = R0 + 1"]);
+		assert_eq!(lift(&[0x71FF]), ["R1 = R1 + 0xFFFFFFFF"]);
+		assert_eq!(lift(&[0x310C]), ["R1 = R1 + R0"]);
+		assert_eq!(lift(&[0x0009]), Vec::<String>::new());
+	}
+
+	#[test]
+	fn post_increment_into_base() {
+		// The loaded value wins over the incremented address
+		assert_eq!(lift(&[0x6116]), ["R1 = [R1].d"]);
+		assert_eq!(lift(&[0x6114]), ["R1 = sx.d([R1].b)"]);
+		assert_eq!(lift(&[0x6216]), ["R2 = [R1].d", "R1 = R1 + 4"]);
+	}
+
+	#[test]
+	fn status_register() {
+		let read = "(((SR & 0xFFFFFCFE) | zx.d(T)) | (zx.d(Q) << 8)) | (zx.d(M) << 9)";
+		let flags = ["T = (SR & 1) != 0", "Q = ((SR u>> 8) & 1) != 0", "M = ((SR u>> 9) & 1) != 0"];
+		assert_eq!(lift(&[0x0102]), [format!("R1 = {}", read)]);
+		assert_eq!(lift(&[0x4103]), ["R1 = R1 - 4".to_string(), format!("[R1].d = {}", read)]);
+		assert_eq!(lift(&[0x410E]), [&["SR = R1"][..], &flags].concat());
+		assert_eq!(lift(&[0x4107]), [&["SR = [R1].d"][..], &flags, &["R1 = R1 + 4"]].concat());
+	}
+
+	#[test]
+	fn divide_step() {
+		assert_eq!(lift(&[0x3104]), [
+			"temp0 = Q",
+			"Q = R1 s< 0",
+			"R1 = (R1 << 1) | zx.d(T)",
+			"temp1 = -zx.q(temp0 == M)",
+			"temp2 = zx.q(R1) + (((-zx.q(R0)) & temp1) | (zx.q(R0) & (~temp1)))",
+			"R1 = low.d(temp2)",
+			"Q = (Q ^ ((temp2 u>> 0x20) != 0)) ^ M",
+			"T = Q == M",
+		]);
+	}
+
+	/// Runs a function of the setup, 16 `div1 R1,R0` and the finish through constant
+	/// propagation. Returns the value left in R0 by the last instruction of the finish
+	fn divide(setup: &[u16], finish: &[u16]) -> Value {
+		let mut words = setup.to_vec();
+		words.extend([0x3014; 16].iter());
+		words.extend(finish);
+		words.extend([0x000B, 0x0009].iter());
+		let mut ws = code_workspace(&words);
+		ws.analyze();
+
+		let ssa = ws.ssa(0x1000).unwrap();
+		let dataflow = Dataflow::new(&ssa, &ws.memory);
+		let last = 0x1000 + (words.len() - 3) * 2;
+		let statement = ssa.blocks().flat_map(|block| &block.statements).filter(|statement| statement.address == last).last().unwrap();
+		dataflow.value(*statement.definitions.last().unwrap())
+	}
+
+	#[test]
+	fn division() {
+		// mov #100,R0; mov #7,R1; shll16 R1; div0u; ...; rotcl R0; extu.w R0,R0
+		assert_eq!(divide(&[0xE064, 0xE107, 0x4128, 0x0019], &[0x4024, 0x600D]), Value::Constant(14));
+
+		// mov #-100,R0; mov #7,R1; shll16 R1; exts.w R0,R0; mov #0,R2; mov R0,R3; rotcl R3; subc R2,R0; div0s R1,R0; ...;
+		// exts.w R0,R0; rotcl R0; addc R2,R0; exts.w R0,R0
+		let setup = [0xE09C, 0xE107, 0x4128, 0x600F, 0xE200, 0x6303, 0x4324, 0x302A, 0x2017];
+		assert_eq!(divide(&setup, &[0x600F, 0x4024, 0x302E, 0x600F]), Value::Constant(-14i32 as u32 as u64));
+	}
+
+	#[test]
+	fn valid_trees() {
+		let arch = SH2E::new();
+		let mut layout = code_layout(&[0; 0x200]);
+		for word in 0..=0xFFFF_u16 {
+			layout.write_memory(0x1000, &word.to_be_bytes()).unwrap();
+			if let Ok((instruction, _)) = arch.disassemble_single(&layout, 0x1000) {
+				for tree in arch.lift(&instruction, 0x1000) {
+					assert!(tree.validate().is_ok(), "{:04X} {}: {} {:?}", word, instruction, tree, tree.validate());
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,412 @@
+use crate::architecture::{BranchKind, Disassembly};
+use crate::function::{BasicBlock, Function};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+
+
+
+/// Kind of control flow transfer between blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+	Fallthrough,		// The block ends without a branch
+	ConditionalTrue,	// Conditional branch taken
+	ConditionalFalse,	// Conditional branch not taken
+	Unconditional,
+	Call,				// Returns to the same block
+	Return,
+	Indirect,			// Target is computed at runtime
+}
+
+
+
+/// Edge leaving a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+	/// Start of the source block
+	pub from: usize,
+	/// Destination address. Outside of the function for calls and tail calls,
+	/// None for returns and unresolved indirect branches
+	pub to: Option<usize>,
+	pub kind: EdgeKind,
+}
+
+
+
+/// Control flow graph of a function
+#[derive(Debug)]
+pub struct Cfg {
+	entry: usize,
+	blocks: Vec<BasicBlock>,	// Ordered by address
+	edges: Vec<Edge>,			// Ordered by source block
+}
+
+
+
+/// Dominator tree of a control flow graph
+#[derive(Debug)]
+pub struct Dominators {
+	entry: usize,
+	idom: BTreeMap<usize, usize>, // Immediate dominator of each reachable block except the entry
+}
+
+
+
+/// Natural loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+	/// Block dominating the whole loop
+	pub header: usize,
+	/// Blocks with a back edge to the header
+	pub latches: Vec<usize>,
+	/// Blocks of the loop including the header
+	pub blocks: BTreeSet<usize>,
+}
+
+
+
+impl Cfg {
+	/// Connects the blocks of the function using the instructions of the disassembly
+	pub fn new(function: &Function, disassembly: &Disassembly) -> Cfg {
+		let mut edges = Vec::new();
+		for block in function.blocks() {
+			let mut address = block.start;
+			// Delay slots left of the current branch
+			let mut in_delay_slot = 0;
+			// Branch ending the block
+			let mut last = None;
+
+			while address < block.end {
+				let (instruction, size) = match disassembly.get(address) {
+					Some(result) => result,
+					None => break,
+				};
+				let info = instruction.info();
+
+				if in_delay_slot > 0 {
+					in_delay_slot -= 1;
+				} else {
+					match info.branch {
+						BranchKind::None | BranchKind::Trap => {}
+						BranchKind::Call(_) => edges.push(Edge {from: block.start, to: disassembly.branch_target(address), kind: EdgeKind::Call}),
+						kind => last = Some((address, kind)),
+					}
+					in_delay_slot = info.delay_slots;
+				}
+				address += size;
+			}
+
+			let next = Some(block.end).filter(|next| function.blocks().iter().any(|block| block.start == *next));
+			match last {
+				Some((address, BranchKind::Jump(_))) => {
+					let to = disassembly.branch_target(address);
+					let kind = if to.is_some() { EdgeKind::Unconditional } else { EdgeKind::Indirect };
+					edges.push(Edge {from: block.start, to, kind});
+				}
+				Some((address, BranchKind::ConditionalJump(_))) => {
+					edges.push(Edge {from: block.start, to: disassembly.branch_target(address), kind: EdgeKind::ConditionalTrue});
+					edges.push(Edge {from: block.start, to: Some(block.end), kind: EdgeKind::ConditionalFalse});
+				}
+				Some((_, BranchKind::Return)) => edges.push(Edge {from: block.start, to: None, kind: EdgeKind::Return}),
+				_ if next.is_some() => edges.push(Edge {from: block.start, to: next, kind: EdgeKind::Fallthrough}),
+				_ => {}
+			}
+		}
+
+		Cfg {
+			entry: function.start(),
+			blocks: function.blocks().to_vec(),
+			edges,
+		}
+	}
+
+	/// Returns the start of the entry block
+	pub fn entry(&self) -> usize {
+		self.entry
+	}
+
+	/// Returns the blocks ordered by address
+	pub fn blocks(&self) -> &[BasicBlock] {
+		&self.blocks
+	}
+
+	/// Returns every edge ordered by source block
+	pub fn edges(&self) -> &[Edge] {
+		&self.edges
+	}
+
+	/// Returns the edges leaving the block
+	pub fn successors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+		self.edges.iter().filter(move |edge| edge.from == block)
+	}
+
+	/// Returns the edges entering the block from within the function
+	pub fn predecessors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+		self.edges.iter().filter(move |edge| edge.to == Some(block) && edge.kind != EdgeKind::Call)
+	}
+
+	/// Returns the blocks control can continue to within the function
+	fn internal_successors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+		self.successors(block)
+			.filter(|edge| edge.kind != EdgeKind::Call)
+			.filter_map(|edge| edge.to)
+			.filter(move |to| self.blocks.iter().any(|block| block.start == *to))
+	}
+
+	/// Returns the reachable blocks in reverse postorder
+	fn reverse_postorder(&self) -> Vec<usize> {
+		let mut visited = BTreeSet::new();
+		let mut order = Vec::new();
+		// Blocks and whether their successors have been pushed
+		let mut stack = vec![(self.entry, false)];
+
+		while let Some((block, expanded)) = stack.pop() {
+			if expanded {
+				order.push(block);
+				continue;
+			}
+			if !visited.insert(block) {
+				continue;
+			}
+			stack.push((block, true));
+			for successor in self.internal_successors(block) {
+				if !visited.contains(&successor) {
+					stack.push((successor, false));
+				}
+			}
+		}
+
+		order.reverse();
+		order
+	}
+
+	/// Computes the dominator tree of the blocks reachable from the entry
+	pub fn dominators(&self) -> Dominators {
+		// Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm"
+		let order = self.reverse_postorder();
+		let index: BTreeMap<usize, usize> = order.iter().enumerate().map(|(i, block)| (*block, i)).collect();
+
+		let mut idom: Vec<Option<usize>> = vec![None; order.len()];
+		if !order.is_empty() {
+			idom[0] = Some(0);
+		}
+
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for i in 1..order.len() {
+				let mut new: Option<usize> = None;
+				for edge in self.predecessors(order[i]) {
+					let p = match index.get(&edge.from) {
+						Some(&p) if idom[p].is_some() => p,
+						_ => continue,
+					};
+					new = Some(match new {
+						None => p,
+						Some(mut a) => {
+							// Walk both up the tree until they meet
+							let mut b = p;
+							while a != b {
+								while a > b {
+									a = idom[a].unwrap();
+								}
+								while b > a {
+									b = idom[b].unwrap();
+								}
+							}
+							a
+						}
+					});
+				}
+				if new.is_some() && idom[i] != new {
+					idom[i] = new;
+					changed = true;
+				}
+			}
+		}
+
+		Dominators {
+			entry: self.entry,
+			idom: (1..order.len()).filter_map(|i| Some((order[i], order[idom[i]?]))).collect(),
+		}
+	}
+
+	/// Finds the natural loops. Back edges to the same header form one loop.
+	pub fn loops(&self) -> Vec<Loop> {
+		let dominators = self.dominators();
+		let mut loops: BTreeMap<usize, Loop> = BTreeMap::new();
+
+		for edge in &self.edges {
+			let header = match edge.to {
+				Some(to) if edge.kind != EdgeKind::Call && dominators.dominates(to, edge.from) => to,
+				_ => continue,
+			};
+
+			let entry = loops.entry(header).or_insert_with(|| Loop {
+				header,
+				latches: Vec::new(),
+				blocks: iter::once(header).collect(),
+			});
+			entry.latches.push(edge.from);
+
+			// Every block reaching the latch without passing the header is part of the loop
+			let mut worklist = vec![edge.from];
+			while let Some(block) = worklist.pop() {
+				if dominators.dominates(header, block) && entry.blocks.insert(block) {
+					worklist.extend(self.predecessors(block).map(|edge| edge.from));
+				}
+			}
+		}
+
+		loops.into_values().collect()
+	}
+
+	/// Returns the dominance frontier of each reachable block: the blocks where its dominance ends
+	pub fn dominance_frontiers(&self, dominators: &Dominators) -> BTreeMap<usize, BTreeSet<usize>> {
+		let mut frontiers: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+		for block in self.reverse_postorder() {
+			let idom = dominators.immediate_dominator(block);
+			let predecessors: BTreeSet<usize> = self.predecessors(block).map(|edge| edge.from).collect();
+			// The entry is also reached from the caller, so a loop back to it makes it a join
+			let joined = predecessors.len() + (block == self.entry) as usize;
+			if joined < 2 {
+				continue;
+			}
+			for predecessor in predecessors {
+				// Walk up from each predecessor until the dominator of the join
+				let mut runner = Some(predecessor);
+				while let Some(current) = runner {
+					if Some(current) == idom || !dominators.dominates(self.entry, current) {
+						break;
+					}
+					frontiers.entry(current).or_default().insert(block);
+					runner = dominators.immediate_dominator(current);
+				}
+			}
+		}
+		frontiers
+	}
+}
+
+
+
+impl Dominators {
+	/// Returns the immediate dominator of the block. None for the entry and unreachable blocks
+	pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+		self.idom.get(&block).cloned()
+	}
+
+	/// Returns the blocks immediately dominated by the block
+	pub fn children(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+		self.idom.iter().filter(move |(_, idom)| **idom == block).map(|(child, _)| *child)
+	}
+
+	/// Returns true if every path from the entry to `block` passes through `dominator`.
+	/// A block dominates itself.
+	pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+		if block != self.entry && !self.idom.contains_key(&block) {
+			return false;
+		}
+
+		let mut current = block;
+		loop {
+			if current == dominator {
+				return true;
+			}
+			match self.idom.get(&current) {
+				Some(&parent) => current = parent,
+				None => return false,
+			}
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::architecture::sh2e::SH2E;
+	use crate::function::Functions;
+	use crate::memory::code_layout;
+	use crate::symbol::Symbols;
+
+	/// Builds the graph of the function at 0x1000 of big-endian SH-2 words
+	fn cfg(words: &[u16]) -> Cfg {
+		let layout = code_layout(words);
+		let mut disassembly = Disassembly::new();
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&SH2E::new(), &layout);
+		let functions = Functions::from_disassembly(&disassembly, &Symbols::new());
+		Cfg::new(functions.get(0x1000).unwrap(), &disassembly)
+	}
+
+	/// Shorthand for an edge
+	fn edge(from: usize, to: Option<usize>, kind: EdgeKind) -> Edge {
+		Edge {from, to, kind}
+	}
+
+	#[test]
+	fn diamond() {
+		// 1000: bt 1008; mov #1,R0; bra 100A; nop
+		// 1008: mov #2,R0
+		// 100A: rts; nop
+		let cfg = cfg(&[0x8902, 0xE001, 0xA001, 0x0009, 0xE002, 0x000B, 0x0009]);
+		assert_eq!(cfg.entry(), 0x1000);
+		assert_eq!(cfg.blocks().iter().map(|block| block.start).collect::<Vec<_>>(), vec![0x1000, 0x1002, 0x1008, 0x100A]);
+		assert_eq!(cfg.edges(), &[
+			edge(0x1000, Some(0x1008), EdgeKind::ConditionalTrue),
+			edge(0x1000, Some(0x1002), EdgeKind::ConditionalFalse),
+			edge(0x1002, Some(0x100A), EdgeKind::Unconditional),
+			edge(0x1008, Some(0x100A), EdgeKind::Fallthrough),
+			edge(0x100A, None, EdgeKind::Return),
+		]);
+		assert_eq!(cfg.predecessors(0x100A).map(|edge| edge.from).collect::<Vec<_>>(), vec![0x1002, 0x1008]);
+
+		let dominators = cfg.dominators();
+		assert_eq!(dominators.immediate_dominator(0x1000), None);
+		assert_eq!(dominators.immediate_dominator(0x100A), Some(0x1000));
+		assert_eq!(dominators.children(0x1000).collect::<Vec<_>>(), vec![0x1002, 0x1008, 0x100A]);
+		assert!(dominators.dominates(0x1000, 0x1008) && !dominators.dominates(0x1002, 0x100A));
+
+		let frontiers = cfg.dominance_frontiers(&dominators);
+		assert_eq!(frontiers.get(&0x1002), Some(&iter::once(0x100A).collect()));
+		assert_eq!(frontiers.get(&0x1008), Some(&iter::once(0x100A).collect()));
+		assert_eq!(frontiers.get(&0x1000), None);
+		assert!(cfg.loops().is_empty());
+	}
+
+	#[test]
+	fn loops() {
+		// 1000: mov #0,R0
+		// 1002: add #1,R0; cmp/eq #0,R0; bf 1002
+		// 1008: rts; nop
+		let cfg = cfg(&[0xE000, 0x7001, 0x8800, 0x8BFC, 0x000B, 0x0009]);
+		assert_eq!(cfg.edges(), &[
+			edge(0x1000, Some(0x1002), EdgeKind::Fallthrough),
+			edge(0x1002, Some(0x1002), EdgeKind::ConditionalTrue),
+			edge(0x1002, Some(0x1008), EdgeKind::ConditionalFalse),
+			edge(0x1008, None, EdgeKind::Return),
+		]);
+		assert_eq!(cfg.loops(), vec![Loop {header: 0x1002, latches: vec![0x1002], blocks: iter::once(0x1002).collect()}]);
+		assert_eq!(cfg.dominance_frontiers(&cfg.dominators()).get(&0x1002), Some(&iter::once(0x1002).collect()));
+	}
+
+	#[test]
+	fn calls() {
+		// 1000: bsr 1008; nop; jmp @R1; nop
+		// 1008: rts; nop
+		let cfg = cfg(&[0xB002, 0x0009, 0x412B, 0x0009, 0x000B, 0x0009]);
+		assert_eq!(cfg.edges(), &[edge(0x1000, Some(0x1008), EdgeKind::Call), edge(0x1000, None, EdgeKind::Indirect)]);
+		assert_eq!(cfg.predecessors(0x1008).count(), 0);
+	}
+
+	#[test]
+	fn loop_to_entry() {
+		// 1000: dt R4; bf 1000; rts; nop
+		let cfg = cfg(&[0x4410, 0x8BFD, 0x000B, 0x0009]);
+		assert_eq!(cfg.loops(), vec![Loop {header: 0x1000, latches: vec![0x1000], blocks: iter::once(0x1000).collect()}]);
+		// The caller is a second predecessor of the entry
+		assert_eq!(cfg.dominance_frontiers(&cfg.dominators()).get(&0x1000), Some(&iter::once(0x1000).collect()));
+	}
+}
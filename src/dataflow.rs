@@ -0,0 +1,353 @@
+use crate::error::Error;
+use crate::il::{Condition, Instruction, InstructionId, Size};
+use crate::memory::{Layout, SectionFlags};
+use crate::ssa::{SsaFunction, SsaVariable, Statement, Variable};
+
+use std::collections::BTreeMap;
+
+
+
+/// Value of a version found by constant propagation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+	Undefined,		// No definition has been evaluated yet
+	Constant(u64),	// Same on every execution, masked to the size of the value
+	Varying,
+}
+
+
+
+/// Memory access whose address is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+	/// Address of the instruction
+	pub from: usize,
+	/// Address accessed
+	pub to: usize,
+	pub size: Size,
+	/// Read or Write
+	pub access: SectionFlags,
+}
+
+
+
+/// Constant register and flag values of a function, and the memory accesses they resolve.
+/// Values on entry are unknown, and memory is only known where it is read-only.
+pub struct Dataflow {
+	values: BTreeMap<SsaVariable, Value>,
+	accesses: Vec<Access>,
+}
+
+
+
+/// Returns the bits of a value of the size
+fn mask(size: Size) -> u64 {
+	match size.bits() {
+		64 => !0,
+		bits => (1 << bits) - 1,
+	}
+}
+
+
+
+/// Interprets the bits as a signed value of the size
+fn signed(value: u64, size: Size) -> i64 {
+	let shift = 64 - size.bits();
+	((value << shift) as i64) >> shift
+}
+
+
+
+/// Reads memory that cannot change while the program runs
+fn read_only(layout: &Layout, address: usize, size: Size) -> Option<u64> {
+	let bytes = size.bits() / 8;
+	layout.check_access(address, bytes, SectionFlags::Read).ok()?;
+	if !matches!(layout.check_access(address, bytes, SectionFlags::Write), Err(Error::ReadOnly)) {
+		return None;
+	}
+	match size {
+		Size::Int8 => layout.read_u8(address).ok().map(u64::from),
+		Size::Int16 => layout.read_u16(address).ok().map(u64::from),
+		Size::Int32 => layout.read_u32(address).ok().map(u64::from),
+		Size::Int64 => layout.read_u64(address).ok(),
+		_ => None,
+	}
+}
+
+
+
+impl Value {
+	/// Combines the values of two paths
+	fn meet(self, other: Value) -> Value {
+		match (self, other) {
+			(Value::Undefined, value) | (value, Value::Undefined) => value,
+			(Value::Constant(a), Value::Constant(b)) if a == b => self,
+			_ => Value::Varying,
+		}
+	}
+
+	pub fn constant(self) -> Option<u64> {
+		match self {
+			Value::Constant(value) => Some(value),
+			_ => None,
+		}
+	}
+}
+
+
+
+impl Dataflow {
+	/// Propagates constants through the function until no value changes
+	pub fn new(ssa: &SsaFunction, layout: &Layout) -> Dataflow {
+		let mut dataflow = Dataflow {
+			values: BTreeMap::new(),
+			accesses: Vec::new(),
+		};
+
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for block in ssa.blocks() {
+				for phi in &block.phis {
+					let value = phi.sources.iter().fold(Value::Undefined, |value, (_, source)| value.meet(dataflow.value(*source)));
+					changed |= dataflow.update(phi.definition, value);
+				}
+
+				for statement in &block.statements {
+					let root = match statement.tree.root() {
+						Some(root) => root,
+						None => continue,
+					};
+					// The register or flag set by the statement, if its value is computed
+					let computed = match statement.tree.get(root) {
+						Some(Instruction::SetRegister(register, value)) => Some((Variable::Register(register.id), *value)),
+						Some(Instruction::SetFlag(flag, value)) => Some((Variable::Flag(flag.0), *value)),
+						_ => None,
+					};
+					// Statements setting a register define it last, after the stack pointer of a `Pop`
+					let last = statement.definitions.len().saturating_sub(1);
+					for (i, definition) in statement.definitions.iter().enumerate() {
+						let value = match computed {
+							Some((variable, value)) if i == last && definition.variable == variable => dataflow.evaluate(statement, value, layout),
+							_ => Value::Varying,
+						};
+						changed |= dataflow.update(*definition, value);
+					}
+				}
+			}
+		}
+
+		for block in ssa.blocks() {
+			for statement in &block.statements {
+				dataflow.resolve(statement, layout);
+			}
+		}
+		dataflow
+	}
+
+	/// Lowers the value of the version. Returns true if it changed
+	fn update(&mut self, variable: SsaVariable, value: Value) -> bool {
+		let old = self.value(variable);
+		let new = old.meet(value);
+		if new == old {
+			return false;
+		}
+		self.values.insert(variable, new);
+		true
+	}
+
+	/// Returns the value of the version. Values on entry are not known
+	pub fn value(&self, variable: SsaVariable) -> Value {
+		if variable.version == 0 {
+			return Value::Varying;
+		}
+		self.values.get(&variable).cloned().unwrap_or(Value::Undefined)
+	}
+
+	/// Returns the accesses at known addresses, ordered by instruction
+	pub fn accesses(&self) -> &[Access] {
+		&self.accesses
+	}
+
+	/// Records the loads and stores of the statement with a constant address
+	fn resolve(&mut self, statement: &Statement, layout: &Layout) {
+		for (id, node) in statement.tree.iter() {
+			let (size, address, access) = match *node {
+				Instruction::Load(size, address) => (size, address, SectionFlags::Read),
+				Instruction::Store(size, address, _) if statement.tree.root() == Some(id) => (size, address, SectionFlags::Write),
+				_ => continue,
+			};
+			if let Value::Constant(to) = self.evaluate(statement, address, layout) {
+				let access = Access {from: statement.address, to: to as usize, size, access};
+				if !self.accesses.contains(&access) {
+					self.accesses.push(access);
+				}
+			}
+		}
+	}
+
+	/// Computes the value of an expression of the statement
+	fn evaluate(&self, statement: &Statement, id: InstructionId, layout: &Layout) -> Value {
+		let tree = &statement.tree;
+		let node = match tree.get(id) {
+			Some(node) => *node,
+			None => return Value::Varying,
+		};
+		let size = match node.size() {
+			Some(size) => size,
+			None => return Value::Varying,
+		};
+		// Floating point values are not tracked
+		if size.is_float() {
+			return Value::Varying;
+		}
+		let size_of = |operand: InstructionId| tree.get(operand).and_then(|node| node.size()).unwrap_or(size);
+
+		let operands: Vec<Value> = match node {
+			Instruction::GetRegister(_) | Instruction::GetFlag(_) => {
+				return statement.uses.iter()
+					.find(|(node, used)| *node == id && used.variable != Variable::Memory)
+					.map_or(Value::Varying, |(_, used)| self.value(*used));
+			}
+			Instruction::Constant(_, value) => return Value::Constant(value & mask(size)),
+			Instruction::Pop(_) => return Value::Varying,
+			_ => node.operands().into_iter().map(|operand| self.evaluate(statement, operand, layout)).collect(),
+		};
+		if operands.contains(&Value::Undefined) {
+			return Value::Undefined;
+		}
+		let values: Vec<u64> = match operands.iter().map(|value| value.constant()).collect() {
+			Some(values) => values,
+			None => return Value::Varying,
+		};
+
+		let result = match node {
+			Instruction::Load(size, _) => match read_only(layout, values[0] as usize, size) {
+				Some(value) => value,
+				None => return Value::Varying,
+			},
+			Instruction::Add(..) => values[0].wrapping_add(values[1]),
+			Instruction::Sub(..) => values[0].wrapping_sub(values[1]),
+			Instruction::Mul(..) => values[0].wrapping_mul(values[1]),
+			Instruction::And(..) => values[0] & values[1],
+			Instruction::Or(..) => values[0] | values[1],
+			Instruction::Xor(..) => values[0] ^ values[1],
+			Instruction::Not(_, _) => !values[0],
+			Instruction::Neg(_, _) => values[0].wrapping_neg(),
+			Instruction::Abs(_, _) => signed(values[0], size).unsigned_abs(),
+			Instruction::ShiftLeft(..) if values[1] < size.bits() as u64 => values[0] << values[1],
+			Instruction::LogicalShiftRight(..) if values[1] < size.bits() as u64 => values[0] >> values[1],
+			Instruction::ShiftLeft(..) | Instruction::LogicalShiftRight(..) => 0,
+			Instruction::ArithmeticShiftRight(..) => (signed(values[0], size) >> values[1].min(63)) as u64,
+			Instruction::RotateLeft(..) | Instruction::RotateRight(..) => {
+				let bits = size.bits() as u64;
+				let mut amount = values[1] % bits;
+				if let Instruction::RotateRight(..) = node {
+					amount = (bits - amount) % bits;
+				}
+				if amount == 0 { values[0] } else { (values[0] << amount) | (values[0] >> (bits - amount)) }
+			}
+			Instruction::SignExtend(_, operand) => signed(values[0], size_of(operand)) as u64,
+			Instruction::ZeroExtend(_, _) | Instruction::Truncate(_, _) | Instruction::BitCast(_, _) => values[0],
+			Instruction::Compare(condition, left, _) => {
+				let operand = size_of(left);
+				if operand.is_float() {
+					return Value::Varying;
+				}
+				let (a, b) = (values[0], values[1]);
+				(match condition {
+					Condition::Equal => a == b,
+					Condition::NotEqual => a != b,
+					Condition::SignedLess => signed(a, operand) < signed(b, operand),
+					Condition::SignedLessEqual => signed(a, operand) <= signed(b, operand),
+					Condition::UnsignedLess => a < b,
+					Condition::UnsignedLessEqual => a <= b,
+				}) as u64
+			}
+			_ => return Value::Varying,
+		};
+		Value::Constant(result & mask(size))
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::Section;
+	use crate::workspace::code_workspace;
+
+	/// Analyzes code at 0x1000 followed by writable RAM at 0xFFFF8000, and propagates constants through the function at 0x1000
+	fn dataflow(words: &[u16]) -> (SsaFunction, Dataflow) {
+		let mut ws = code_workspace(words);
+		ws.memory.add_section(Section::from_raw(0xFFFF8000, vec![0; 0x100], SectionFlags::ReadWrite | SectionFlags::Data)).unwrap();
+		ws.analyze();
+		let ssa = ws.ssa(0x1000).unwrap();
+		let dataflow = Dataflow::new(&ssa, &ws.memory);
+		(ssa, dataflow)
+	}
+
+	/// Returns the value of the version with the name, e.g. `R1#2`
+	fn value(ssa: &SsaFunction, dataflow: &Dataflow, name: &str) -> Value {
+		let definition = ssa.blocks()
+			.flat_map(|block| block.phis.iter().map(|phi| phi.definition).chain(block.statements.iter().flat_map(|statement| statement.definitions.iter().cloned())))
+			.find(|definition| definition.to_string() == name)
+			.unwrap();
+		dataflow.value(definition)
+	}
+
+	#[test]
+	fn literal_pool() {
+		// 1000: mov.l @(100C),R1; mov #4,R2; add R2,R1; mov.l @R1,R0
+		// 1008: rts; nop; .long FFFF8000
+		let (ssa, dataflow) = dataflow(&[0xD102, 0xE204, 0x312C, 0x6012, 0x000B, 0x0009, 0xFFFF, 0x8000]);
+		assert_eq!(value(&ssa, &dataflow, "R1#1"), Value::Constant(0xFFFF8000));
+		assert_eq!(value(&ssa, &dataflow, "R2#1"), Value::Constant(4));
+		assert_eq!(value(&ssa, &dataflow, "R1#2"), Value::Constant(0xFFFF8004));
+		// RAM can change, so only the address of the load is known
+		assert_eq!(value(&ssa, &dataflow, "R0#1"), Value::Varying);
+		assert_eq!(dataflow.accesses(), [
+			Access {from: 0x1000, to: 0x100C, size: Size::Int32, access: SectionFlags::Read},
+			Access {from: 0x1006, to: 0xFFFF8004, size: Size::Int32, access: SectionFlags::Read},
+		]);
+	}
+
+	#[test]
+	fn gbr() {
+		// 1000: mov.l @(100C),R0; ldc R0,GBR; mov.b R0,@(4,GBR); mov.l @(4,GBR),R0
+		// 1008: rts; nop; .long FFFF8000
+		let (_, dataflow) = dataflow(&[0xD002, 0x401E, 0xC004, 0xC601, 0x000B, 0x0009, 0xFFFF, 0x8000]);
+		assert_eq!(dataflow.accesses(), [
+			Access {from: 0x1000, to: 0x100C, size: Size::Int32, access: SectionFlags::Read},
+			Access {from: 0x1004, to: 0xFFFF8004, size: Size::Int8, access: SectionFlags::Write},
+			Access {from: 0x1006, to: 0xFFFF8004, size: Size::Int32, access: SectionFlags::Read},
+		]);
+	}
+
+	#[test]
+	fn join() {
+		// 1000: mov #1,R1; mov #3,R0; bt 100A; mov #2,R0
+		// 1008: mov #1,R1
+		// 100A: rts; nop
+		let (ssa, dataflow) = dataflow(&[0xE101, 0xE003, 0x8901, 0xE002, 0xE101, 0x000B, 0x0009]);
+		let phis: Vec<String> = ssa.blocks().flat_map(|block| &block.phis).map(|phi| phi.to_string()).collect();
+		assert_eq!(phis, ["R0#3 = phi(R0#1, R0#2)", "R1#3 = phi(R1#1, R1#2)"]);
+		assert_eq!(value(&ssa, &dataflow, "R0#3"), Value::Varying);
+		assert_eq!(value(&ssa, &dataflow, "R1#3"), Value::Constant(1));
+	}
+
+	#[test]
+	fn loop_counter() {
+		// 1000: mov #3,R4
+		// 1002: dt R4; bf 1002; rts; nop
+		let (ssa, dataflow) = dataflow(&[0xE403, 0x4410, 0x8BFD, 0x000B, 0x0009]);
+		assert_eq!(value(&ssa, &dataflow, "R4#1"), Value::Constant(3));
+		assert_eq!(value(&ssa, &dataflow, "R4#2"), Value::Varying);
+		assert_eq!(value(&ssa, &dataflow, "R4#3"), Value::Varying);
+		assert_eq!(value(&ssa, &dataflow, "T#1"), Value::Varying);
+		// Nothing is known on entry
+		assert_eq!(dataflow.value(SsaVariable {variable: Variable::Memory, version: 0}), Value::Varying);
+		assert!(dataflow.accesses().is_empty());
+	}
+}
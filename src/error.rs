@@ -1,9 +1,44 @@
+use std::fmt;
+use std::io;
 use std::result;
 
 #[derive(Debug)]
 pub enum Error {
 	InvalidInstruction,
 	InvalidMemory, // Invalid address or there not enough memory for the operation
+	NotExecutable, // Code was accessed in a section without the Execute flag
+	ReadOnly, // Memory was written in a section without the Write flag
+	OverlappingSection, // A section was added over an existing one
+	InvalidArgument(String), // Bad user input, e.g. a malformed command line option
+	InvalidFormat(String), // Malformed or unsupported input file
+	InvalidRecord(usize, String), // Malformed S-record or Intel HEX record (line number, reason)
+	DuplicateSymbol(String), // The name already belongs to another address
+	InvalidIl(String), // Malformed IL tree
+	Io(io::Error),
 }
 
-pub type Result<T> = result::Result<T, Error>;
\ No newline at end of file
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::InvalidInstruction => write!(f, "invalid instruction"),
+			Error::InvalidMemory => write!(f, "invalid memory access"),
+			Error::NotExecutable => write!(f, "memory is not executable"),
+			Error::ReadOnly => write!(f, "memory is read-only"),
+			Error::OverlappingSection => write!(f, "section overlaps an existing section"),
+			Error::InvalidArgument(message) => write!(f, "{}", message),
+			Error::InvalidFormat(message) => write!(f, "{}", message),
+			Error::InvalidRecord(line, message) => write!(f, "line {}: {}", line, message),
+			Error::DuplicateSymbol(name) => write!(f, "symbol '{}' already exists", name),
+			Error::InvalidIl(message) => write!(f, "invalid IL: {}", message),
+			Error::Io(err) => write!(f, "{}", err),
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Error {
+		Error::Io(err)
+	}
+}
+
+pub type Result<T> = result::Result<T, Error>;
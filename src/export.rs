@@ -0,0 +1,211 @@
+use crate::error::{Error, Result};
+use crate::memory::Section;
+
+use std::fmt::Write;
+
+
+
+/// Largest raw image that is written, including gaps
+pub const MAX_RAW_SIZE: usize = 0x1000_0000;
+
+
+
+/// Writes the sections as one contiguous image starting at the first section.
+/// Gaps between sections are filled with `fill`. Sections must be ordered by address.
+/// Fails if the image would be larger than `MAX_RAW_SIZE`, e.g. when ROM and RAM are far apart.
+pub fn raw<'a>(sections: impl IntoIterator<Item = &'a Section>, fill: u8) -> Result<Vec<u8>> {
+	let sections: Vec<&Section> = sections.into_iter().collect();
+	let (base, end) = match (sections.first(), sections.last()) {
+		(Some(first), Some(last)) => (first.address(), last.end()),
+		_ => return Ok(Vec::new()),
+	};
+	if end - base > MAX_RAW_SIZE {
+		return Err(Error::InvalidArgument(format!("raw image from 0x{:X} to 0x{:X} is too large", base, end)));
+	}
+
+	let mut image = Vec::with_capacity(end - base);
+	for section in sections {
+		image.resize(section.address() - base, fill);
+		image.extend_from_slice(section.data());
+	}
+	Ok(image)
+}
+
+
+
+/// Splits the sections into chunks of at most `width` bytes that do not cross
+/// a multiple of `boundary`
+fn chunks<'a>(sections: impl IntoIterator<Item = &'a Section>, width: usize, boundary: usize) -> Vec<(usize, &'a [u8])> {
+	let mut chunks = Vec::new();
+	for section in sections {
+		let mut address = section.address();
+		let mut data = section.data();
+		while !data.is_empty() {
+			let len = width.min(data.len()).min(boundary - address % boundary);
+			chunks.push((address, &data[..len]));
+			address += len;
+			data = &data[len..];
+		}
+	}
+	chunks
+}
+
+
+
+/// Writes the sections as Motorola S-records with `width` data bytes per record.
+/// The address size (S1/S2/S3) is picked from the highest address. The entry
+/// point is written to the termination record.
+pub fn srec<'a>(sections: impl IntoIterator<Item = &'a Section>, width: usize, entry: Option<usize>) -> Result<String> {
+	let sections: Vec<&Section> = sections.into_iter().collect();
+	let end = sections.iter().map(|section| section.end().saturating_sub(1)).max().unwrap_or(0).max(entry.unwrap_or(0));
+	let (data_kind, end_kind, address_size) = match end {
+		0..=0xFFFF => (1, 9, 2),
+		0x10000..=0xFF_FFFF => (2, 8, 3),
+		_ => (3, 7, 4),
+	};
+	if end as u64 > 0xFFFF_FFFF {
+		return Err(Error::InvalidArgument("address does not fit in an S-record".to_string()));
+	}
+	if width == 0 || width > 255 - address_size - 1 {
+		return Err(Error::InvalidArgument(format!("invalid S-record width {}", width)));
+	}
+
+	let mut out = String::new();
+	srec_record(&mut out, 0, 2, 0, &[]);
+	for (address, data) in chunks(sections, width, usize::MAX) {
+		srec_record(&mut out, data_kind, address_size, address, data);
+	}
+	srec_record(&mut out, end_kind, address_size, entry.unwrap_or(0), &[]);
+	Ok(out)
+}
+
+fn srec_record(out: &mut String, kind: u8, address_size: usize, address: usize, data: &[u8]) {
+	let mut bytes = vec![(address_size + data.len() + 1) as u8];
+	bytes.extend((0..address_size).rev().map(|i| (address >> (i * 8)) as u8));
+	bytes.extend_from_slice(data);
+
+	let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+	write!(out, "S{}", kind).unwrap();
+	for byte in bytes {
+		write!(out, "{:02X}", byte).unwrap();
+	}
+	writeln!(out, "{:02X}", checksum).unwrap();
+}
+
+
+
+/// Writes the sections as Intel HEX with `width` data bytes per record, using
+/// extended linear address records above 64 KiB. The entry point is written as
+/// a start linear address record.
+pub fn ihex<'a>(sections: impl IntoIterator<Item = &'a Section>, width: usize, entry: Option<usize>) -> Result<String> {
+	if width == 0 || width > 255 {
+		return Err(Error::InvalidArgument(format!("invalid Intel HEX width {}", width)));
+	}
+
+	let mut out = String::new();
+	let mut upper = 0;
+	for (address, data) in chunks(sections, width, 0x10000) {
+		if address as u64 + data.len() as u64 > 0x1_0000_0000 {
+			return Err(Error::InvalidArgument("address does not fit in Intel HEX".to_string()));
+		}
+		if address >> 16 != upper {
+			upper = address >> 16;
+			ihex_record(&mut out, 0x04, 0, &[(upper >> 8) as u8, upper as u8]);
+		}
+		ihex_record(&mut out, 0x00, address as u16, data);
+	}
+	if let Some(entry) = entry {
+		if entry as u64 > 0xFFFF_FFFF {
+			return Err(Error::InvalidArgument("entry point does not fit in Intel HEX".to_string()));
+		}
+		ihex_record(&mut out, 0x05, 0, &(entry as u32).to_be_bytes());
+	}
+	ihex_record(&mut out, 0x01, 0, &[]);
+	Ok(out)
+}
+
+fn ihex_record(out: &mut String, kind: u8, address: u16, data: &[u8]) {
+	let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+	bytes.extend_from_slice(data);
+
+	let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+	out.push(':');
+	for byte in bytes {
+		write!(out, "{:02X}", byte).unwrap();
+	}
+	writeln!(out, "{:02X}", checksum).unwrap();
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::loader::{ihex, srec};
+	use crate::memory::{Layout, SectionFlags};
+
+	/// Maps two sections of patterned data
+	fn layout(first: usize, second: usize) -> Layout {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(first, (0..1000u32).map(|i| (i * 7 + 3) as u8).collect(), SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(second, (0..77u32).map(|i| (i * 13) as u8).collect(), SectionFlags::Read)).unwrap();
+		layout
+	}
+
+	#[test]
+	fn round_trip() {
+		// Record boundaries, 64 KiB segments and 32-bit addresses
+		for (first, second) in [(0x100, 0x800), (0xFFF0, 0x2_0000), (0x0FFF_FF00, 0xFFFF_FF00)] {
+			let layout = layout(first, second);
+			let expected: Vec<(usize, &[u8])> = layout.sections().map(|section| (section.address(), section.data())).collect();
+			for width in [1, 16, 32, 200] {
+				let srec = srec::parse(&srec(layout.sections(), width, Some(first)).unwrap()).unwrap();
+				let ihex = ihex::parse(&ihex(layout.sections(), width, Some(first)).unwrap()).unwrap();
+				for records in [srec, ihex] {
+					assert_eq!(records.entry, Some(first));
+					let sections = records.sections(SectionFlags::Read);
+					let sections: Vec<(usize, &[u8])> = sections.iter().map(|section| (section.address(), section.data())).collect();
+					assert_eq!(sections, expected);
+				}
+			}
+		}
+
+		let layout = layout(0x100, 0x800);
+		let image = raw(layout.sections(), 0xAA).unwrap();
+		let mut loaded = Layout::new();
+		loaded.add_section(Section::from_raw(0x100, image.clone(), SectionFlags::Read)).unwrap();
+		for section in layout.sections() {
+			let mut buffer = vec![0; section.data().len()];
+			loaded.read_memory(section.address(), &mut buffer).unwrap();
+			assert_eq!(buffer, section.data());
+		}
+		assert_eq!(raw(loaded.sections(), 0xAA).unwrap(), image);
+	}
+
+	#[test]
+	fn formats() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x10, vec![1, 2], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x14, vec![3], SectionFlags::Read)).unwrap();
+		assert_eq!(raw(layout.sections(), 0).unwrap(), vec![1, 2, 0, 0, 3]);
+		assert_eq!(srec(layout.sections(), 2, None).unwrap(), "S0030000FC\nS10500100102E7\nS104001403E4\nS9030000FC\n");
+		assert_eq!(ihex(layout.sections(), 2, Some(0x10)).unwrap(), ":020010000102EB\n:0100140003E8\n:0400000500000010E7\n:00000001FF\n");
+		assert!(srec(layout.sections(), 0, None).is_err());
+		assert!(srec(layout.sections(), 253, None).is_err());
+		assert!(ihex(layout.sections(), 256, None).is_err());
+		assert!(ihex(layout.sections(), 2, Some(0x1_0000_0000)).is_err());
+	}
+
+	#[test]
+	fn patched_image() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x10, vec![0; 4], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x1A, vec![7; 2], SectionFlags::Read)).unwrap();
+		layout.write_u16(0x12, 0x1122).unwrap();
+		assert_eq!(raw(layout.sections(), 0xFF).unwrap(), vec![0, 0, 0x11, 0x22, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 7, 7]);
+
+		// ROM and RAM far apart
+		layout.add_section(Section::from_raw(0xFFFF8000, vec![0; 4], SectionFlags::ReadWrite)).unwrap();
+		assert!(matches!(raw(layout.sections(), 0xFF), Err(Error::InvalidArgument(_))));
+	}
+}
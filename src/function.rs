@@ -0,0 +1,278 @@
+use crate::architecture::{BranchKind, Disassembly};
+use crate::symbol::Symbols;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+
+
+/// Range of instructions that execute in sequence. Control flow only enters
+/// at the start and only leaves after the last instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicBlock {
+	pub start: usize,
+	/// Address after the last instruction
+	pub end: usize,
+}
+
+
+
+/// Code reachable from a start address without following calls
+#[derive(Debug)]
+pub struct Function {
+	start: usize,
+	name: String,
+	blocks: Vec<BasicBlock>,	// Ordered by address
+	returns: Vec<usize>,		// Addresses of the return instructions
+}
+
+
+
+/// Functions of a workspace ordered by start address
+#[derive(Default)]
+pub struct Functions {
+	functions: BTreeMap<usize, Function>,
+}
+
+
+
+impl BasicBlock {
+	pub fn contains(&self, address: usize) -> bool {
+		address >= self.start && address < self.end
+	}
+}
+
+
+
+impl Function {
+	/// Collects the blocks reachable from the start. Jumps to the start of another
+	/// function in `starts` are tail calls and are not followed.
+	fn build(start: usize, name: String, disassembly: &Disassembly, starts: &BTreeSet<usize>) -> Function {
+		let mut visited: BTreeMap<usize, usize> = BTreeMap::new(); // Size of the instructions by address
+		let mut leaders = BTreeSet::new();
+		let mut returns = Vec::new();
+		let mut worklist = vec![start];
+		leaders.insert(start);
+
+		while let Some(mut address) = worklist.pop() {
+			// Delay slots left before the sequence ends
+			let mut remaining: Option<usize> = None;
+			// Delay slots left of the current branch, and whether the block ends after them
+			let mut in_delay_slot = 0;
+			let mut ends_block = false;
+
+			while remaining != Some(0) && !visited.contains_key(&address) {
+				let (instruction, size) = match disassembly.get(address) {
+					Some(result) => result,
+					None => break,
+				};
+				let info = instruction.info();
+				let current = address;
+				visited.insert(address, size);
+				address += size;
+				remaining = remaining.map(|count| count - 1);
+
+				if in_delay_slot > 0 {
+					in_delay_slot -= 1;
+					if in_delay_slot == 0 && ends_block {
+						leaders.insert(address);
+					}
+					continue;
+				}
+
+				match info.branch {
+					BranchKind::Jump(_) | BranchKind::ConditionalJump(_) => {
+						if let Some(target) = disassembly.branch_target(current) {
+							if target == start || !starts.contains(&target) {
+								leaders.insert(target);
+								worklist.push(target);
+							}
+						}
+					}
+					BranchKind::Return => returns.push(current),
+					_ => {}
+				}
+
+				// Calls and traps return to the same block
+				ends_block = !matches!(info.branch, BranchKind::None | BranchKind::Call(_) | BranchKind::Trap);
+				if ends_block && info.delay_slots == 0 {
+					leaders.insert(address);
+				}
+				if !info.falls_through() {
+					remaining = Some(info.delay_slots);
+				}
+				in_delay_slot = info.delay_slots;
+			}
+		}
+
+		let mut blocks: Vec<BasicBlock> = Vec::new();
+		for (&address, &size) in &visited {
+			match blocks.last_mut() {
+				Some(block) if block.end == address && !leaders.contains(&address) => block.end += size,
+				_ => blocks.push(BasicBlock {start: address, end: address + size}),
+			}
+		}
+		returns.sort_unstable();
+
+		Function {
+			start,
+			name,
+			blocks,
+			returns,
+		}
+	}
+
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Returns the basic blocks ordered by address
+	pub fn blocks(&self) -> &[BasicBlock] {
+		&self.blocks
+	}
+
+	/// Returns the block containing the address
+	pub fn block_at(&self, address: usize) -> Option<&BasicBlock> {
+		let i = self.blocks.partition_point(|block| block.start <= address).checked_sub(1)?;
+		Some(&self.blocks[i]).filter(|block| block.contains(address))
+	}
+
+	/// Returns the addresses of the return instructions
+	pub fn returns(&self) -> &[usize] {
+		&self.returns
+	}
+
+	/// Returns the address after the last instruction
+	pub fn end(&self) -> usize {
+		self.blocks.last().map_or(self.start, |block| block.end)
+	}
+
+	/// Returns true if the address belongs to one of the blocks
+	pub fn contains(&self, address: usize) -> bool {
+		self.block_at(address).is_some()
+	}
+}
+
+
+
+impl Functions {
+	pub fn new() -> Functions {
+		Functions::default()
+	}
+
+	/// Creates a function at every decoded entry point and call target of the disassembly.
+	/// Functions are named after the symbol at their start, or `sub_<address>`.
+	pub fn from_disassembly(disassembly: &Disassembly, symbols: &Symbols) -> Functions {
+		let starts: BTreeSet<usize> = disassembly.entry_points()
+			.chain(disassembly.call_targets())
+			.filter(|address| disassembly.instruction_at(*address).is_some())
+			.collect();
+
+		let mut functions = BTreeMap::new();
+		for &start in &starts {
+			let name = symbols.name(start).map_or_else(|| format!("sub_{:X}", start), str::to_string);
+			functions.insert(start, Function::build(start, name, disassembly, &starts));
+		}
+		Functions {functions}
+	}
+
+	/// Returns the function starting at the address
+	pub fn get(&self, start: usize) -> Option<&Function> {
+		self.functions.get(&start)
+	}
+
+	/// Renames the function starting at the address. Returns false if there is none
+	pub fn rename(&mut self, start: usize, name: &str) -> bool {
+		match self.functions.get_mut(&start) {
+			Some(function) => {
+				function.name = name.to_string();
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Returns the function containing the address. Code shared by several
+	/// functions belongs to the closest one starting before it.
+	pub fn containing(&self, address: usize) -> Option<&Function> {
+		self.functions.range(..=address).rev()
+			.chain(self.functions.range(address + 1..))
+			.map(|(_, function)| function)
+			.find(|function| function.contains(address))
+	}
+
+	/// Returns the functions ordered by start address
+	pub fn iter(&self) -> impl Iterator<Item = &Function> {
+		self.functions.values()
+	}
+
+	pub fn len(&self) -> usize {
+		self.functions.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.functions.is_empty()
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::architecture::Architecture;
+	use crate::architecture::sh2e::SH2E;
+	use crate::memory::code_layout;
+	use crate::symbol::{SymbolSource, Symbols};
+
+	/// Disassembles big-endian SH-2 words at 0x1000 from 0x1000 and the prologues
+	fn disassemble(words: &[u16]) -> Disassembly {
+		let layout = code_layout(words);
+		let arch = SH2E::new();
+		let mut disassembly = Disassembly::new();
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&arch, &layout);
+		for address in arch.find_prologues(&layout, &disassembly) {
+			disassembly.add_entry_point(address);
+		}
+		disassembly.analyze(&arch, &layout);
+		disassembly
+	}
+
+	#[test]
+	fn functions() {
+		// 1000: mov.l @(101C),R1; jsr @R1; nop; bsr 100E; nop; bra 100A; nop
+		// 100E: mov.l R14,@-R15; bt 1016; rts; nop; rts; nop; data; .long 1020
+		// 1020: rts; nop
+		// 1024: mov.l R8,@-R15; sts.l PR,@-R15; rts; nop
+		let disassembly = disassemble(&[0xD106, 0x410B, 0x0009, 0xB002, 0x0009, 0xAFFE, 0x0009, 0x2FE6, 0x8901, 0x000B, 0x0009, 0x000B, 0x0009,
+			0xFFFF, 0x0000, 0x1020, 0x000B, 0x0009, 0x2F86, 0x4F22, 0x000B, 0x0009]);
+		let functions = Functions::from_disassembly(&disassembly, &Symbols::new());
+
+		let starts: Vec<usize> = functions.iter().map(|function| function.start()).collect();
+		assert_eq!(starts, vec![0x1000, 0x100E, 0x1020, 0x1024]);
+		assert_eq!(functions.get(0x1000).unwrap().blocks(), &[BasicBlock {start: 0x1000, end: 0x100A}, BasicBlock {start: 0x100A, end: 0x100E}]);
+
+		let function = functions.get(0x100E).unwrap();
+		assert_eq!(function.blocks(), &[BasicBlock {start: 0x100E, end: 0x1012}, BasicBlock {start: 0x1012, end: 0x1016}, BasicBlock {start: 0x1016, end: 0x101A}]);
+		assert_eq!(function.returns(), &[0x1012, 0x1016]);
+		assert_eq!(function.end(), 0x101A);
+		assert_eq!(function.name(), "sub_100E");
+		assert_eq!(functions.containing(0x1014).unwrap().start(), 0x100E);
+		assert!(functions.containing(0x101C).is_none());
+	}
+
+	#[test]
+	fn rename() {
+		let mut symbols = Symbols::new();
+		symbols.insert(0x1000, "start", SymbolSource::User).unwrap();
+		let mut functions = Functions::from_disassembly(&disassemble(&[0x000B, 0x0009]), &symbols);
+		assert_eq!(functions.get(0x1000).unwrap().name(), "start");
+		assert!(functions.rename(0x1000, "main"));
+		assert!(!functions.rename(0x1002, "main"));
+		assert_eq!(functions.get(0x1000).unwrap().name(), "main");
+	}
+}
@@ -1,12 +1,32 @@
 // Intermediate Language
 
+use crate::architecture::RegisterId;
+use crate::error::{Error, Result};
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InstructionId(usize);
 
+impl InstructionId {
+	/// Returns the index of the node in its tree
+	pub fn index(&self) -> usize {
+		self.0
+	}
+}
+
+#[derive(Debug)]
 pub struct InstructionTree {
 	nodes: Vec<Instruction>,
 	root: Option<InstructionId>,
 }
 
+impl Default for InstructionTree {
+	fn default() -> InstructionTree {
+		InstructionTree::new()
+	}
+}
+
 impl InstructionTree {
 	/// Creates an empty tree
 	pub fn new() -> InstructionTree {
@@ -22,27 +42,784 @@ impl InstructionTree {
 		InstructionId(self.nodes.len() - 1)
 	}
 
-	/// Sets the root node from an instruction ID. This MUST be a valid ID.
-	pub fn set_root(&mut self, id: InstructionId) {
+	/// Sets the root node from an instruction ID. Fails if the tree has no node with the ID
+	pub fn set_root(&mut self, id: InstructionId) -> Result<()> {
+		if id.0 >= self.nodes.len() {
+			return Err(Error::InvalidIl(format!("root {} is not a node of the tree", id.0)));
+		}
 		self.root = Some(id);
+		Ok(())
 	}
 
 	/// Adds an instruction node and sets it as root
 	pub fn add_root(&mut self, instruction: Instruction) {
 		let id = self.add_node(instruction);
-		self.set_root(id);
+		self.root = Some(id);
+	}
+
+	/// Creates a tree from a single root node
+	pub fn from_root(instruction: Instruction) -> InstructionTree {
+		let mut tree = InstructionTree::new();
+		tree.add_root(instruction);
+		tree
+	}
+
+	/// Returns the root node ID
+	pub fn root(&self) -> Option<InstructionId> {
+		self.root
+	}
+
+	/// Returns the node with the ID
+	pub fn get(&self, id: InstructionId) -> Option<&Instruction> {
+		self.nodes.get(id.0)
+	}
+
+	/// Returns the nodes in the order they were added
+	pub fn nodes(&self) -> &[Instruction] {
+		&self.nodes
+	}
+
+	/// Returns the nodes with their IDs in the order they were added
+	pub fn iter(&self) -> impl Iterator<Item = (InstructionId, &Instruction)> {
+		self.nodes.iter().enumerate().map(|(index, node)| (InstructionId(index), node))
+	}
+
+	/// Returns a copy of the expression with the ID as its root. The nodes are renumbered
+	pub fn subtree(&self, id: InstructionId) -> InstructionTree {
+		let mut tree = InstructionTree::new();
+		if let Some(root) = self.copy_node(id, &mut tree) {
+			tree.root = Some(root);
+		}
+		tree
+	}
+
+	/// Copies the node and its operands into the other tree
+	fn copy_node(&self, id: InstructionId, tree: &mut InstructionTree) -> Option<InstructionId> {
+		let node = self.get(id)?;
+		let mut operands = Vec::new();
+		for operand in node.operands() {
+			operands.push(self.copy_node(operand, tree)?);
+		}
+		let mut operands = operands.into_iter();
+		Some(tree.add_node(node.map_operands(|_| operands.next().unwrap())))
+	}
+
+	/// Checks that the tree is a single statement whose operands are well-typed expressions.
+	/// Every node except the root must be the operand of exactly one node added after it.
+	pub fn validate(&self) -> Result<()> {
+		let root = self.root.ok_or_else(|| Error::InvalidIl("tree has no root".to_string()))?;
+		if root.0 >= self.nodes.len() {
+			return Err(Error::InvalidIl(format!("root {} is not a node of the tree", root.0)));
+		}
+		if !self.nodes[root.0].is_statement() {
+			return Err(Error::InvalidIl(format!("root {} is not a statement", root.0)));
+		}
+
+		let mut used = vec![false; self.nodes.len()];
+		for (index, node) in self.nodes.iter().enumerate() {
+			for operand in node.operands() {
+				if operand.0 >= index {
+					return Err(Error::InvalidIl(format!("node {} uses node {} which is not added before it", index, operand.0)));
+				}
+				if used[operand.0] {
+					return Err(Error::InvalidIl(format!("node {} is used more than once", operand.0)));
+				}
+				used[operand.0] = true;
+			}
+		}
+		if let Some(unused) = (0..self.nodes.len()).find(|index| !used[*index] && *index != root.0) {
+			return Err(Error::InvalidIl(format!("node {} is not part of the statement", unused)));
+		}
+		if used[root.0] {
+			return Err(Error::InvalidIl(format!("root {} is used as an operand", root.0)));
+		}
+
+		self.check_statement(root)
+	}
+
+	/// Checks the operands of the statement
+	fn check_statement(&self, id: InstructionId) -> Result<()> {
+		match self.nodes[id.0] {
+			Instruction::SetRegister(register, value) => self.expect(value, register.size),
+			Instruction::SetFlag(_, value) => self.expect(value, Size::Bool),
+			Instruction::Store(size, address, value) => {
+				self.expect_address(address)?;
+				self.expect(value, size)
+			}
+			Instruction::Push(size, value) => self.expect(value, size),
+			Instruction::Jump(target) | Instruction::Call(target) | Instruction::Return(target) => self.expect_address(target),
+			Instruction::If(condition, target) => {
+				self.expect(condition, Size::Bool)?;
+				self.expect_address(target)
+			}
+			Instruction::Trap(_) | Instruction::Unimplemented => Ok(()),
+			_ => Err(Error::InvalidIl(format!("node {} is not a statement", id.0))),
+		}
+	}
+
+	/// Fails unless the expression has the size
+	fn expect(&self, id: InstructionId, size: Size) -> Result<()> {
+		let actual = self.size_of(id)?;
+		if actual != size {
+			return Err(Error::InvalidIl(format!("node {} is {:?} but {:?} is expected", id.0, actual, size)));
+		}
+		Ok(())
+	}
+
+	/// Fails unless the expression is an integer that can be used as an address
+	fn expect_address(&self, id: InstructionId) -> Result<()> {
+		match self.size_of(id)? {
+			Size::Int32 | Size::Int64 => Ok(()),
+			size => Err(Error::InvalidIl(format!("node {} is {:?} and cannot be an address", id.0, size))),
+		}
+	}
+
+	/// Fails unless the expression is an integer
+	fn expect_integer(&self, id: InstructionId) -> Result<Size> {
+		match self.size_of(id)? {
+			Size::Bool | Size::Float32 => Err(Error::InvalidIl(format!("node {} is not an integer", id.0))),
+			size => Ok(size),
+		}
+	}
+
+	/// Checks the expression and its operands and returns the size of its value
+	fn size_of(&self, id: InstructionId) -> Result<Size> {
+		let invalid = |message: &str| Err(Error::InvalidIl(format!("node {}: {}", id.0, message)));
+
+		match self.nodes[id.0] {
+			Instruction::GetRegister(register) => Ok(register.size),
+			Instruction::GetFlag(_) => Ok(Size::Bool),
+			Instruction::Constant(size, value) => {
+				if size.bits() < 64 && value >> size.bits() != 0 {
+					return invalid("constant does not fit in its size");
+				}
+				Ok(size)
+			}
+			Instruction::Load(size, address) => {
+				self.expect_address(address)?;
+				if size == Size::Bool {
+					return invalid("memory cannot hold a Bool");
+				}
+				Ok(size)
+			}
+			Instruction::Pop(size) => {
+				if size == Size::Bool {
+					return invalid("the stack cannot hold a Bool");
+				}
+				Ok(size)
+			}
+
+			Instruction::Add(size, left, right)
+			| Instruction::Sub(size, left, right)
+			| Instruction::Mul(size, left, right)
+			| Instruction::Div(size, left, right) => {
+				if size == Size::Bool {
+					return invalid("arithmetic on Bool");
+				}
+				self.expect(left, size)?;
+				self.expect(right, size)?;
+				Ok(size)
+			}
+			Instruction::And(size, left, right)
+			| Instruction::Or(size, left, right)
+			| Instruction::Xor(size, left, right) => {
+				if size.is_float() {
+					return invalid("bitwise operation on Float32");
+				}
+				self.expect(left, size)?;
+				self.expect(right, size)?;
+				Ok(size)
+			}
+			Instruction::Not(size, value) => {
+				if size.is_float() {
+					return invalid("bitwise operation on Float32");
+				}
+				self.expect(value, size)?;
+				Ok(size)
+			}
+			Instruction::Neg(size, value) | Instruction::Abs(size, value) => {
+				if size == Size::Bool {
+					return invalid("arithmetic on Bool");
+				}
+				self.expect(value, size)?;
+				Ok(size)
+			}
+
+			Instruction::ShiftLeft(size, value, amount)
+			| Instruction::LogicalShiftRight(size, value, amount)
+			| Instruction::ArithmeticShiftRight(size, value, amount)
+			| Instruction::RotateLeft(size, value, amount)
+			| Instruction::RotateRight(size, value, amount) => {
+				if size == Size::Bool || size.is_float() {
+					return invalid("shift of a value that is not an integer");
+				}
+				self.expect(value, size)?;
+				self.expect(amount, Size::Int32)?;
+				Ok(size)
+			}
+
+			Instruction::SignExtend(size, value) | Instruction::ZeroExtend(size, value) => {
+				let from = match self.nodes[id.0] {
+					// Only zero extension gives Bool a numeric value
+					Instruction::ZeroExtend(..) if self.size_of(value)? == Size::Bool => Size::Bool,
+					_ => self.expect_integer(value)?,
+				};
+				if size == Size::Bool || size.is_float() || from.bits() >= size.bits() {
+					return invalid("extension must widen an integer");
+				}
+				Ok(size)
+			}
+			Instruction::Truncate(size, value) => {
+				let from = self.expect_integer(value)?;
+				if size == Size::Bool || size.is_float() || from.bits() <= size.bits() {
+					return invalid("truncation must narrow an integer");
+				}
+				Ok(size)
+			}
+			Instruction::IntToFloat(size, value) => {
+				self.expect_integer(value)?;
+				if !size.is_float() {
+					return invalid("conversion to a size that is not floating point");
+				}
+				Ok(size)
+			}
+			Instruction::FloatToInt(size, value) => {
+				if !self.size_of(value)?.is_float() {
+					return invalid("conversion of a value that is not floating point");
+				}
+				if size == Size::Bool || size.is_float() {
+					return invalid("conversion to a size that is not an integer");
+				}
+				Ok(size)
+			}
+			Instruction::BitCast(size, value) => {
+				if self.size_of(value)?.bits() != size.bits() || size == Size::Bool {
+					return invalid("bit cast between sizes of different width");
+				}
+				Ok(size)
+			}
+
+			Instruction::Compare(condition, left, right) => {
+				let size = self.size_of(left)?;
+				self.expect(right, size)?;
+				let ordered = !matches!(condition, Condition::Equal | Condition::NotEqual);
+				let unsigned = matches!(condition, Condition::UnsignedLess | Condition::UnsignedLessEqual);
+				if (ordered && size == Size::Bool) || (unsigned && size.is_float()) {
+					return invalid("condition does not apply to the operands");
+				}
+				Ok(Size::Bool)
+			}
+
+			_ => invalid("statement used as a value"),
+		}
+	}
+
+	/// Returns a displayable form that shows the version of each register and flag, e.g. `R1#2 = R1#1 + 4`.
+	/// `versions` returns the version read by a node or defined by the root.
+	pub fn display_versions<'a>(&'a self, versions: &'a dyn Fn(InstructionId) -> Option<usize>) -> TreeDisplay<'a> {
+		TreeDisplay {
+			tree: self,
+			versions,
+		}
+	}
+}
+
+/// IL tree formatted with versioned names, see `InstructionTree::display_versions`
+pub struct TreeDisplay<'a> {
+	tree: &'a InstructionTree,
+	versions: &'a dyn Fn(InstructionId) -> Option<usize>,
+}
+
+impl TreeDisplay<'_> {
+	/// Writes the expression in infix style
+	fn write_node(&self, f: &mut fmt::Formatter, id: InstructionId) -> fmt::Result {
+		let node = match self.tree.get(id) {
+			Some(node) => node,
+			None => return write!(f, "<invalid {}>", id.0),
+		};
+
+		match *node {
+			Instruction::SetRegister(register, value) => {
+				self.write_name(f, &register, id)?;
+				write!(f, " = ")?;
+				self.write_node(f, value)
+			}
+			Instruction::SetFlag(flag, value) => {
+				self.write_name(f, &flag, id)?;
+				write!(f, " = ")?;
+				self.write_node(f, value)
+			}
+			Instruction::Store(size, address, value) => {
+				write!(f, "[")?;
+				self.write_node(f, address)?;
+				write!(f, "]{} = ", size)?;
+				self.write_node(f, value)
+			}
+			Instruction::Push(size, value) => self.write_call(f, &format!("push{}", size), &[value]),
+			Instruction::Jump(target) => self.write_call(f, "jump", &[target]),
+			Instruction::If(condition, target) => {
+				write!(f, "if (")?;
+				self.write_node(f, condition)?;
+				write!(f, ") jump(")?;
+				self.write_node(f, target)?;
+				write!(f, ")")
+			}
+			Instruction::Call(target) => self.write_call(f, "call", &[target]),
+			Instruction::Return(target) => self.write_call(f, "return", &[target]),
+			Instruction::Trap(number) => write!(f, "trap({})", number),
+			Instruction::Unimplemented => write!(f, "unimplemented"),
+
+			Instruction::GetRegister(register) => self.write_name(f, &register, id),
+			Instruction::GetFlag(flag) => self.write_name(f, &flag, id),
+			Instruction::Constant(Size::Bool, value) => write!(f, "{}", value != 0),
+			Instruction::Constant(Size::Float32, value) => write!(f, "{:?}", f32::from_bits(value as u32)),
+			Instruction::Constant(_, value) if value < 10 => write!(f, "{}", value),
+			Instruction::Constant(_, value) => write!(f, "0x{:X}", value),
+			Instruction::Load(size, address) => {
+				write!(f, "[")?;
+				self.write_node(f, address)?;
+				write!(f, "]{}", size)
+			}
+			Instruction::Pop(size) => write!(f, "pop{}", size),
+
+			Instruction::Add(size, left, right) => self.write_infix(f, if size.is_float() { "f+" } else { "+" }, left, right),
+			Instruction::Sub(size, left, right) => self.write_infix(f, if size.is_float() { "f-" } else { "-" }, left, right),
+			Instruction::Mul(size, left, right) => self.write_infix(f, if size.is_float() { "f*" } else { "*" }, left, right),
+			Instruction::Div(size, left, right) => self.write_infix(f, if size.is_float() { "f/" } else { "/" }, left, right),
+			Instruction::And(_, left, right) => self.write_infix(f, "&", left, right),
+			Instruction::Or(_, left, right) => self.write_infix(f, "|", left, right),
+			Instruction::Xor(_, left, right) => self.write_infix(f, "^", left, right),
+			Instruction::Not(_, value) => self.write_prefix(f, "~", value),
+			Instruction::Neg(_, value) => self.write_prefix(f, "-", value),
+			Instruction::Abs(_, value) => self.write_call(f, "abs", &[value]),
+
+			Instruction::ShiftLeft(_, value, amount) => self.write_infix(f, "<<", value, amount),
+			Instruction::LogicalShiftRight(_, value, amount) => self.write_infix(f, "u>>", value, amount),
+			Instruction::ArithmeticShiftRight(_, value, amount) => self.write_infix(f, "s>>", value, amount),
+			Instruction::RotateLeft(_, value, amount) => self.write_call(f, "rol", &[value, amount]),
+			Instruction::RotateRight(_, value, amount) => self.write_call(f, "ror", &[value, amount]),
+
+			Instruction::SignExtend(size, value) => self.write_call(f, &format!("sx{}", size), &[value]),
+			Instruction::ZeroExtend(size, value) => self.write_call(f, &format!("zx{}", size), &[value]),
+			Instruction::Truncate(size, value) => self.write_call(f, &format!("low{}", size), &[value]),
+			Instruction::IntToFloat(size, value) => self.write_call(f, &format!("float{}", size), &[value]),
+			Instruction::FloatToInt(size, value) => self.write_call(f, &format!("int{}", size), &[value]),
+			Instruction::BitCast(size, value) => self.write_call(f, &format!("bits{}", size), &[value]),
+
+			Instruction::Compare(condition, left, right) => {
+				let float = matches!(self.tree.get(left), Some(node) if node.size() == Some(Size::Float32));
+				let operator = match condition {
+					Condition::Equal => "==",
+					Condition::NotEqual => "!=",
+					Condition::SignedLess if float => "f<",
+					Condition::SignedLessEqual if float => "f<=",
+					Condition::SignedLess => "s<",
+					Condition::SignedLessEqual => "s<=",
+					Condition::UnsignedLess => "u<",
+					Condition::UnsignedLessEqual => "u<=",
+				};
+				self.write_infix(f, operator, left, right)
+			}
+		}
+	}
+
+	/// Writes a register or flag name with its version
+	fn write_name(&self, f: &mut fmt::Formatter, name: &dyn fmt::Display, id: InstructionId) -> fmt::Result {
+		match (self.versions)(id) {
+			Some(version) => write!(f, "{}#{}", name, version),
+			None => write!(f, "{}", name),
+		}
+	}
+
+	/// Writes an operand of an infix or prefix operator, in parentheses if it is an operation itself
+	fn write_operand(&self, f: &mut fmt::Formatter, id: InstructionId) -> fmt::Result {
+		let nested = matches!(self.tree.get(id), Some(node) if node.is_infix());
+		if nested {
+			write!(f, "(")?;
+		}
+		self.write_node(f, id)?;
+		if nested {
+			write!(f, ")")?;
+		}
+		Ok(())
+	}
+
+	fn write_infix(&self, f: &mut fmt::Formatter, operator: &str, left: InstructionId, right: InstructionId) -> fmt::Result {
+		self.write_operand(f, left)?;
+		write!(f, " {} ", operator)?;
+		self.write_operand(f, right)
+	}
+
+	fn write_prefix(&self, f: &mut fmt::Formatter, operator: &str, value: InstructionId) -> fmt::Result {
+		write!(f, "{}", operator)?;
+		self.write_operand(f, value)
+	}
+
+	fn write_call(&self, f: &mut fmt::Formatter, name: &str, arguments: &[InstructionId]) -> fmt::Result {
+		write!(f, "{}(", name)?;
+		for (i, argument) in arguments.iter().enumerate() {
+			if i > 0 {
+				write!(f, ", ")?;
+			}
+			self.write_node(f, *argument)?;
+		}
+		write!(f, ")")
+	}
+}
+
+impl fmt::Display for TreeDisplay<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.tree.root {
+			Some(root) => self.write_node(f, root),
+			None => write!(f, "<empty>"),
+		}
+	}
+}
+
+impl fmt::Display for InstructionTree {
+	/// Writes the statement at the root, e.g. `R1 = [R2 + 4].d`
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.display_versions(&|_| None).fmt(f)
+	}
+}
+
+/// Size and type of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Size {
+	Bool, // Flag or result of a comparison
+	Int8,
+	Int16,
+	Int32,
+	Int64,
+	Float32,
+}
+
+impl Size {
+	/// Returns the integer size with the amount of bytes
+	pub fn from_bytes(bytes: usize) -> Option<Size> {
+		match bytes {
+			1 => Some(Size::Int8),
+			2 => Some(Size::Int16),
+			4 => Some(Size::Int32),
+			8 => Some(Size::Int64),
+			_ => None,
+		}
+	}
+
+	/// Returns the width in bits
+	pub fn bits(self) -> usize {
+		match self {
+			Size::Bool => 1,
+			Size::Int8 => 8,
+			Size::Int16 => 16,
+			Size::Int32 | Size::Float32 => 32,
+			Size::Int64 => 64,
+		}
+	}
+
+	pub fn is_float(self) -> bool {
+		self == Size::Float32
+	}
+}
+
+impl fmt::Display for Size {
+	/// Writes the suffix of sized operations, e.g. `.d` for Int32
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let suffix = match self {
+			Size::Bool => "",
+			Size::Int8 => ".b",
+			Size::Int16 => ".w",
+			Size::Int32 => ".d",
+			Size::Int64 => ".q",
+			Size::Float32 => ".s",
+		};
+		write!(f, "{}", suffix)
 	}
 }
 
-pub struct Register;
+/// Register holding a value of the size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Register {
+	pub id: RegisterId,
+	pub size: Size,
+}
+
+/// First ID of the temporaries, above any architecture register
+pub const TEMPORARY_ID: u16 = 0xFF00;
+
+const TEMPORARY_NAMES: [&str; 4] = ["temp0", "temp1", "temp2", "temp3"];
 
+impl Register {
+	pub fn new(id: RegisterId, size: Size) -> Register {
+		Register {id, size}
+	}
+
+	/// Returns a temporary register. Temporaries hold intermediate values between
+	/// the statements of one architecture instruction. Panics if the index is above 3.
+	pub fn temporary(index: usize, size: Size) -> Register {
+		Register {
+			id: RegisterId {id: TEMPORARY_ID + index as u16, name: TEMPORARY_NAMES[index]},
+			size,
+		}
+	}
+
+	pub fn is_temporary(&self) -> bool {
+		self.id.id >= TEMPORARY_ID
+	}
+}
+
+/// Single-bit status flag, e.g. the SH T bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Flag(pub RegisterId);
+
+/// Relation tested by `Compare`. Floating point values use the signed relations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+	Equal,
+	NotEqual,
+	SignedLess,
+	SignedLessEqual,
+	UnsignedLess,
+	UnsignedLessEqual,
+}
+
+/// IL node. Statements are roots of a tree and expressions compute values for them.
+/// The `Size` of an operation is the size of its result; operations on `Float32` are floating point.
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
+	// Statements
 	SetRegister(Register, InstructionId), // Register = Expression
-	
-	Load(InstructionId), // Load from memory
-	Store(InstructionId, InstructionId), // Store into memory
-	Push(InstructionId),
-	Pop(InstructionId),
-
-	ConstantInt32(u32),
-}
\ No newline at end of file
+	SetFlag(Flag, InstructionId),
+	Store(Size, InstructionId, InstructionId), // Store into memory (address, value)
+	Push(Size, InstructionId),
+	Jump(InstructionId), // Continue at the address
+	If(InstructionId, InstructionId), // Continue at the address (second) if the condition (first) is true
+	Call(InstructionId), // Call the address and continue after the instruction
+	Return(InstructionId), // Return to the address
+	Trap(u32), // Software exception with the vector number
+	Unimplemented, // The architecture instruction cannot be expressed
+
+	// Values
+	GetRegister(Register),
+	GetFlag(Flag),
+	Constant(Size, u64), // Floating point constants hold the bits of the value
+	Load(Size, InstructionId), // Load from memory
+	Pop(Size), // Value popped from the stack
+
+	// Arithmetic and logic. Both operands have the size of the result
+	Add(Size, InstructionId, InstructionId),
+	Sub(Size, InstructionId, InstructionId),
+	Mul(Size, InstructionId, InstructionId),
+	Div(Size, InstructionId, InstructionId),
+	And(Size, InstructionId, InstructionId),
+	Or(Size, InstructionId, InstructionId),
+	Xor(Size, InstructionId, InstructionId),
+	Not(Size, InstructionId),
+	Neg(Size, InstructionId),
+	Abs(Size, InstructionId),
+
+	// Shifts by an Int32 amount
+	ShiftLeft(Size, InstructionId, InstructionId),
+	LogicalShiftRight(Size, InstructionId, InstructionId),
+	ArithmeticShiftRight(Size, InstructionId, InstructionId),
+	RotateLeft(Size, InstructionId, InstructionId),
+	RotateRight(Size, InstructionId, InstructionId),
+
+	// Conversions to the size
+	SignExtend(Size, InstructionId),
+	ZeroExtend(Size, InstructionId), // Also converts Bool to an integer
+	Truncate(Size, InstructionId), // Keeps the low bits
+	IntToFloat(Size, InstructionId), // Signed integer to floating point
+	FloatToInt(Size, InstructionId), // Floating point to signed integer, rounding toward zero
+	BitCast(Size, InstructionId), // Same bits as another type of the same width
+
+	Compare(Condition, InstructionId, InstructionId), // Bool result of comparing two values of the same size
+}
+
+impl fmt::Display for Register {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.id.name)
+	}
+}
+
+impl fmt::Display for Flag {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0.name)
+	}
+}
+
+impl Instruction {
+	/// Returns true if the node can only be the root of a tree
+	pub fn is_statement(&self) -> bool {
+		matches!(self, Instruction::SetRegister(..) | Instruction::SetFlag(..) | Instruction::Store(..)
+			| Instruction::Push(..) | Instruction::Jump(_) | Instruction::If(..) | Instruction::Call(_)
+			| Instruction::Return(_) | Instruction::Trap(_) | Instruction::Unimplemented)
+	}
+
+	/// Returns the IDs of the operand nodes
+	pub fn operands(&self) -> Vec<InstructionId> {
+		match *self {
+			Instruction::SetRegister(_, a) | Instruction::SetFlag(_, a) | Instruction::Push(_, a)
+			| Instruction::Jump(a) | Instruction::Call(a) | Instruction::Return(a)
+			| Instruction::Load(_, a) | Instruction::Not(_, a) | Instruction::Neg(_, a) | Instruction::Abs(_, a)
+			| Instruction::SignExtend(_, a) | Instruction::ZeroExtend(_, a) | Instruction::Truncate(_, a)
+			| Instruction::IntToFloat(_, a) | Instruction::FloatToInt(_, a) | Instruction::BitCast(_, a) => vec![a],
+
+			Instruction::Store(_, a, b) | Instruction::If(a, b)
+			| Instruction::Add(_, a, b) | Instruction::Sub(_, a, b) | Instruction::Mul(_, a, b) | Instruction::Div(_, a, b)
+			| Instruction::And(_, a, b) | Instruction::Or(_, a, b) | Instruction::Xor(_, a, b)
+			| Instruction::ShiftLeft(_, a, b) | Instruction::LogicalShiftRight(_, a, b) | Instruction::ArithmeticShiftRight(_, a, b)
+			| Instruction::RotateLeft(_, a, b) | Instruction::RotateRight(_, a, b)
+			| Instruction::Compare(_, a, b) => vec![a, b],
+
+			Instruction::Trap(_) | Instruction::Unimplemented | Instruction::GetRegister(_) | Instruction::GetFlag(_)
+			| Instruction::Constant(..) | Instruction::Pop(_) => Vec::new(),
+		}
+	}
+
+	/// Returns a copy of the node with every operand replaced, in the order of `operands`
+	pub fn map_operands<F: FnMut(InstructionId) -> InstructionId>(&self, mut f: F) -> Instruction {
+		match *self {
+			Instruction::SetRegister(register, a) => Instruction::SetRegister(register, f(a)),
+			Instruction::SetFlag(flag, a) => Instruction::SetFlag(flag, f(a)),
+			Instruction::Store(size, a, b) => Instruction::Store(size, f(a), f(b)),
+			Instruction::Push(size, a) => Instruction::Push(size, f(a)),
+			Instruction::Jump(a) => Instruction::Jump(f(a)),
+			Instruction::If(a, b) => Instruction::If(f(a), f(b)),
+			Instruction::Call(a) => Instruction::Call(f(a)),
+			Instruction::Return(a) => Instruction::Return(f(a)),
+			Instruction::Load(size, a) => Instruction::Load(size, f(a)),
+			Instruction::Add(size, a, b) => Instruction::Add(size, f(a), f(b)),
+			Instruction::Sub(size, a, b) => Instruction::Sub(size, f(a), f(b)),
+			Instruction::Mul(size, a, b) => Instruction::Mul(size, f(a), f(b)),
+			Instruction::Div(size, a, b) => Instruction::Div(size, f(a), f(b)),
+			Instruction::And(size, a, b) => Instruction::And(size, f(a), f(b)),
+			Instruction::Or(size, a, b) => Instruction::Or(size, f(a), f(b)),
+			Instruction::Xor(size, a, b) => Instruction::Xor(size, f(a), f(b)),
+			Instruction::Not(size, a) => Instruction::Not(size, f(a)),
+			Instruction::Neg(size, a) => Instruction::Neg(size, f(a)),
+			Instruction::Abs(size, a) => Instruction::Abs(size, f(a)),
+			Instruction::ShiftLeft(size, a, b) => Instruction::ShiftLeft(size, f(a), f(b)),
+			Instruction::LogicalShiftRight(size, a, b) => Instruction::LogicalShiftRight(size, f(a), f(b)),
+			Instruction::ArithmeticShiftRight(size, a, b) => Instruction::ArithmeticShiftRight(size, f(a), f(b)),
+			Instruction::RotateLeft(size, a, b) => Instruction::RotateLeft(size, f(a), f(b)),
+			Instruction::RotateRight(size, a, b) => Instruction::RotateRight(size, f(a), f(b)),
+			Instruction::SignExtend(size, a) => Instruction::SignExtend(size, f(a)),
+			Instruction::ZeroExtend(size, a) => Instruction::ZeroExtend(size, f(a)),
+			Instruction::Truncate(size, a) => Instruction::Truncate(size, f(a)),
+			Instruction::IntToFloat(size, a) => Instruction::IntToFloat(size, f(a)),
+			Instruction::FloatToInt(size, a) => Instruction::FloatToInt(size, f(a)),
+			Instruction::BitCast(size, a) => Instruction::BitCast(size, f(a)),
+			Instruction::Compare(condition, a, b) => Instruction::Compare(condition, f(a), f(b)),
+			other => other,
+		}
+	}
+
+	/// Returns the size of the value, as declared by the node. None for statements
+	pub fn size(&self) -> Option<Size> {
+		match *self {
+			Instruction::GetRegister(register) => Some(register.size),
+			Instruction::GetFlag(_) | Instruction::Compare(..) => Some(Size::Bool),
+			Instruction::Constant(size, _) | Instruction::Load(size, _) | Instruction::Pop(size)
+			| Instruction::Add(size, ..) | Instruction::Sub(size, ..) | Instruction::Mul(size, ..) | Instruction::Div(size, ..)
+			| Instruction::And(size, ..) | Instruction::Or(size, ..) | Instruction::Xor(size, ..)
+			| Instruction::Not(size, _) | Instruction::Neg(size, _) | Instruction::Abs(size, _)
+			| Instruction::ShiftLeft(size, ..) | Instruction::LogicalShiftRight(size, ..) | Instruction::ArithmeticShiftRight(size, ..)
+			| Instruction::RotateLeft(size, ..) | Instruction::RotateRight(size, ..)
+			| Instruction::SignExtend(size, _) | Instruction::ZeroExtend(size, _) | Instruction::Truncate(size, _)
+			| Instruction::IntToFloat(size, _) | Instruction::FloatToInt(size, _) | Instruction::BitCast(size, _) => Some(size),
+			_ => None,
+		}
+	}
+
+	/// Returns true if the node is written with an infix or prefix operator
+	fn is_infix(&self) -> bool {
+		matches!(self, Instruction::Add(..) | Instruction::Sub(..) | Instruction::Mul(..) | Instruction::Div(..)
+			| Instruction::And(..) | Instruction::Or(..) | Instruction::Xor(..) | Instruction::Not(..) | Instruction::Neg(..)
+			| Instruction::ShiftLeft(..) | Instruction::LogicalShiftRight(..) | Instruction::ArithmeticShiftRight(..)
+			| Instruction::Compare(..))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Returns an integer register
+	fn register(id: u16, name: &'static str) -> Register {
+		Register::new(RegisterId {id, name}, Size::Int32)
+	}
+
+	/// Builds `R1 = [R2 + 4].d`
+	fn load() -> InstructionTree {
+		let mut tree = InstructionTree::new();
+		let base = tree.add_node(Instruction::GetRegister(register(2, "R2")));
+		let offset = tree.add_node(Instruction::Constant(Size::Int32, 4));
+		let address = tree.add_node(Instruction::Add(Size::Int32, base, offset));
+		let value = tree.add_node(Instruction::Load(Size::Int32, address));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), value));
+		tree
+	}
+
+	#[test]
+	fn display() {
+		let tree = load();
+		assert_eq!(tree.to_string(), "R1 = [R2 + 4].d");
+		let versions = |id: InstructionId| Some(if id == tree.root().unwrap() { 2 } else { 1 });
+		assert_eq!(tree.display_versions(&versions).to_string(), "R1#2 = [R2#1 + 4].d");
+		assert_eq!(tree.subtree(InstructionId(2)).to_string(), "R2 + 4");
+
+		let mut tree = InstructionTree::new();
+		let t = tree.add_node(Instruction::GetFlag(Flag(RegisterId {id: 0x100, name: "T"})));
+		let condition = tree.add_node(Instruction::Not(Size::Bool, t));
+		let target = tree.add_node(Instruction::Constant(Size::Int32, 0x1000));
+		tree.add_root(Instruction::If(condition, target));
+		assert_eq!(tree.to_string(), "if (~T) jump(0x1000)");
+	}
+
+	#[test]
+	fn validate() {
+		assert!(load().validate().is_ok());
+		assert!(InstructionTree::from_root(Instruction::Unimplemented).validate().is_ok());
+
+		let invalid = |tree: &InstructionTree| match tree.validate() {
+			Err(Error::InvalidIl(message)) => message,
+			result => panic!("{:?}", result),
+		};
+		assert_eq!(invalid(&InstructionTree::new()), "tree has no root");
+		assert!(InstructionTree::new().set_root(InstructionId(0)).is_err());
+
+		// Values are not statements
+		let tree = InstructionTree::from_root(Instruction::Constant(Size::Int32, 1));
+		assert_eq!(invalid(&tree), "root 0 is not a statement");
+
+		let mut tree = InstructionTree::new();
+		let value = tree.add_node(Instruction::GetRegister(register(1, "R1")));
+		tree.add_node(Instruction::GetRegister(register(2, "R2")));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), value));
+		assert_eq!(invalid(&tree), "node 1 is not part of the statement");
+
+		let mut tree = InstructionTree::new();
+		let value = tree.add_node(Instruction::GetRegister(register(1, "R1")));
+		let sum = tree.add_node(Instruction::Add(Size::Int32, value, value));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), sum));
+		assert_eq!(invalid(&tree), "node 0 is used more than once");
+
+		let mut tree = InstructionTree::new();
+		let value = tree.add_node(Instruction::Constant(Size::Int16, 1));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), value));
+		assert_eq!(invalid(&tree), "node 0 is Int16 but Int32 is expected");
+
+		let mut tree = InstructionTree::new();
+		let value = tree.add_node(Instruction::Constant(Size::Int8, 0x100));
+		let value = tree.add_node(Instruction::ZeroExtend(Size::Int32, value));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), value));
+		assert_eq!(invalid(&tree), "node 0: constant does not fit in its size");
+
+		let mut tree = InstructionTree::new();
+		let value = tree.add_node(Instruction::GetRegister(register(1, "R1")));
+		let value = tree.add_node(Instruction::Truncate(Size::Int32, value));
+		tree.add_root(Instruction::SetRegister(register(1, "R1"), value));
+		assert_eq!(invalid(&tree), "node 1: truncation must narrow an integer");
+	}
+}
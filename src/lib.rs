@@ -0,0 +1,13 @@
+pub mod architecture;
+pub mod cfg;
+pub mod error;
+pub mod export;
+pub mod function;
+pub mod loader;
+pub mod memory;
+pub mod symbol;
+pub mod workspace;
+pub mod xref;
+pub mod il;
+pub mod ssa;
+pub mod dataflow;
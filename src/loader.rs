@@ -0,0 +1,230 @@
+use crate::architecture;
+use crate::error::{Error, Result};
+use crate::memory::{Section, SectionFlags};
+use crate::workspace::Workspace;
+
+use std::cmp;
+use std::fs;
+use std::path::Path;
+
+
+pub mod elf;
+pub mod ihex;
+pub mod srec;
+
+
+
+/// File formats that can be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	Raw,
+	Elf,
+	SRecord,
+	IntelHex,
+}
+
+impl Format {
+	pub fn from_name(name: &str) -> Option<Format> {
+		match name.to_lowercase().as_str() {
+			"raw" | "bin" => Some(Format::Raw),
+			"elf" => Some(Format::Elf),
+			"srec" | "mot" | "s19" => Some(Format::SRecord),
+			"ihex" | "hex" => Some(Format::IntelHex),
+			_ => None,
+		}
+	}
+
+	/// Guesses the format from the file contents
+	pub fn detect(data: &[u8]) -> Format {
+		if elf::is_elf(data) {
+			return Format::Elf;
+		}
+
+		let start = data.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(0);
+		match &data[start..] {
+			[b'S', b'0'..=b'9', ..] => Format::SRecord,
+			[b':', ..] => Format::IntelHex,
+			_ => Format::Raw,
+		}
+	}
+}
+
+
+
+/// Data and start address read from a record-based format
+#[derive(Default)]
+pub struct Records {
+	/// Data records in file order
+	pub data: Vec<(usize, Vec<u8>)>,
+	pub entry: Option<usize>,
+}
+
+impl Records {
+	/// Coalesces the data records into sections of contiguous memory.
+	/// Where records overlap, later records take precedence.
+	pub fn sections(&self, flags: SectionFlags) -> Vec<Section> {
+		let mut ranges: Vec<(usize, usize)> = self.data.iter()
+			.filter(|(_, bytes)| !bytes.is_empty())
+			.map(|(address, bytes)| (*address, address + bytes.len()))
+			.collect();
+		ranges.sort();
+
+		// Merge touching and overlapping ranges
+		let mut chunks: Vec<(usize, Vec<u8>)> = Vec::new();
+		for (start, end) in ranges {
+			match chunks.last_mut() {
+				Some((chunk_start, chunk)) if *chunk_start + chunk.len() >= start => {
+					let len = cmp::max(chunk.len(), end - *chunk_start);
+					chunk.resize(len, 0);
+				}
+				_ => chunks.push((start, vec![0; end - start])),
+			}
+		}
+
+		// Copy the data in file order
+		for (address, bytes) in self.data.iter().filter(|(_, bytes)| !bytes.is_empty()) {
+			let i = chunks.partition_point(|(start, _)| start <= address);
+			if let Some((start, chunk)) = i.checked_sub(1).and_then(|i| chunks.get_mut(i)) {
+				let offset = address - *start;
+				chunk[offset..offset + bytes.len()].copy_from_slice(bytes);
+			}
+		}
+
+		chunks.into_iter().map(|(address, bytes)| Section::from_raw(address, bytes, flags)).collect()
+	}
+}
+
+
+
+/// Parses pairs of hexadecimal digits
+#[allow(clippy::manual_is_multiple_of)] // usize::is_multiple_of needs Rust 1.87
+pub(crate) fn parse_hex(text: &str, line: usize) -> Result<Vec<u8>> {
+	if text.len() % 2 != 0 || !text.is_ascii() {
+		return Err(Error::InvalidRecord(line, "odd number of hex digits".to_string()));
+	}
+
+	(0..text.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| Error::InvalidRecord(line, "invalid hex digit".to_string())))
+		.collect()
+}
+
+
+
+/// Options for loading an image into a new workspace
+#[derive(Default)]
+pub struct LoadOptions {
+	/// Architecture name. See `architecture::from_name`. If None, the
+	/// architecture is taken from the file or defaults to SH2E
+	pub arch: Option<String>,
+	/// Address of the first byte of a raw image
+	pub base: usize,
+	/// Format of the file. If None, it is detected from the contents
+	pub format: Option<Format>,
+}
+
+
+
+/// Largest section created from a file or the command line
+pub const MAX_SECTION_SIZE: usize = 0x1000_0000;
+
+
+
+/// Flags of sections loaded from ROM images
+pub fn rom_flags() -> SectionFlags {
+	SectionFlags::Read | SectionFlags::Execute | SectionFlags::Code
+}
+
+
+
+/// Loads a raw binary at `base` as a single read-only, executable section
+pub fn load_raw(ws: &mut Workspace, data: Vec<u8>, base: usize) -> Result<()> {
+	ws.memory.add_section(Section::from_raw(base, data, rom_flags()).with_name("rom"))?;
+	Ok(())
+}
+
+
+
+/// Adds the sections and entry point of a record-based format
+pub fn load_records(ws: &mut Workspace, records: &Records) -> Result<()> {
+	for section in records.sections(rom_flags()) {
+		ws.memory.add_section(section)?;
+	}
+	ws.entry_points.extend(records.entry);
+	Ok(())
+}
+
+
+
+/// Creates a workspace from a file. Unless a format is given, it is detected
+/// from the contents; unrecognized files are loaded as raw binaries.
+pub fn load_file(path: &Path, options: &LoadOptions) -> Result<Workspace> {
+	let data = fs::read(path)?;
+	let format = options.format.unwrap_or_else(|| Format::detect(&data));
+
+	let arch = match &options.arch {
+		Some(name) => Some(architecture::from_name(name)
+			.ok_or_else(|| Error::InvalidArgument(format!("unknown architecture '{}'", name)))?),
+		None => None,
+	};
+
+	if format == Format::Elf {
+		let mut ws = elf::load(&data)?;
+		if let Some(arch) = arch {
+			ws.arch = arch;
+		}
+		return Ok(ws);
+	}
+
+	let mut ws = Workspace::new(arch.unwrap_or_else(|| Box::new(architecture::sh2e::SH2E::new())));
+	match format {
+		Format::SRecord => load_records(&mut ws, &srec::parse(&text(data)?)?)?,
+		Format::IntelHex => load_records(&mut ws, &ihex::parse(&text(data)?)?)?,
+		_ => load_raw(&mut ws, data, options.base)?,
+	}
+	Ok(ws)
+}
+
+
+
+fn text(data: Vec<u8>) -> Result<String> {
+	String::from_utf8(data).map_err(|_| Error::InvalidFormat("file is not text".to_string()))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detect() {
+		assert_eq!(Format::detect(b"\x7FELF\x01\x02\x01"), Format::Elf);
+		assert_eq!(Format::detect(b"\r\nS00600004844521B\n"), Format::SRecord);
+		assert_eq!(Format::detect(b":00000001FF\n"), Format::IntelHex);
+		assert_eq!(Format::detect(&[0x4F, 0x22, 0x2F, 0xE6]), Format::Raw);
+		assert_eq!(Format::detect(b""), Format::Raw);
+		assert_eq!(Format::from_name("S19"), Some(Format::SRecord));
+		assert_eq!(Format::from_name("coff"), None);
+	}
+
+	#[test]
+	fn raw() {
+		let mut ws = Workspace::new(Box::new(architecture::sh2e::SH2E::new()));
+		load_raw(&mut ws, vec![0x4F, 0x22], 0x1000).unwrap();
+		let section = ws.memory.get_section_at(0x1001).unwrap();
+		assert_eq!((section.address(), section.name(), section.flags()), (0x1000, Some("rom"), rom_flags()));
+		assert!(load_raw(&mut ws, vec![0], 0x1001).is_err());
+	}
+
+	#[test]
+	fn coalesce_records() {
+		// Touching and overlapping records merge, later records overwrite earlier ones
+		let records = Records {
+			data: vec![(0x11, vec![7]), (0x10, vec![1, 2]), (0x0, vec![9; 4]), (0x12, vec![3]), (0x4, vec![5]), (0x20, vec![])],
+			entry: None,
+		};
+		let sections = records.sections(SectionFlags::Read);
+		let sections: Vec<(usize, &[u8])> = sections.iter().map(|section| (section.address(), section.data())).collect();
+		assert_eq!(sections, vec![(0x0, &[9, 9, 9, 9, 5][..]), (0x10, &[1, 2, 3][..])]);
+	}
+}
@@ -0,0 +1,376 @@
+use crate::architecture;
+use crate::error::{Error, Result};
+use crate::loader::MAX_SECTION_SIZE;
+use crate::memory::{Endianness, Section, SectionFlags};
+use crate::symbol::SymbolSource;
+use crate::workspace::Workspace;
+
+
+
+const MAGIC: &[u8] = b"\x7FELF";
+
+const CLASS_32: u8 = 1;
+const DATA_LSB: u8 = 1;
+const DATA_MSB: u8 = 2;
+
+const MACHINE_SH: u16 = 42;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0b1;
+const PF_W: u32 = 0b10;
+const PF_R: u32 = 0b100;
+
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+
+const STB_LOCAL: u8 = 0;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+
+
+/// Returns true if the data starts with the ELF magic
+pub fn is_elf(data: &[u8]) -> bool {
+	data.starts_with(MAGIC)
+}
+
+
+
+/// Bounds-checked reader for ELF structures
+struct Reader<'a> {
+	data: &'a [u8],
+	endianness: Endianness,
+}
+
+impl<'a> Reader<'a> {
+	fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+		self.data.get(offset..offset.checked_add(len).ok_or_else(truncated)?).ok_or_else(truncated)
+	}
+
+	fn u8(&self, offset: usize) -> Result<u8> {
+		Ok(self.bytes(offset, 1)?[0])
+	}
+
+	fn u16(&self, offset: usize) -> Result<u16> {
+		let mut buffer = [0; 2];
+		buffer.copy_from_slice(self.bytes(offset, 2)?);
+		Ok(match self.endianness {
+			Endianness::Big => u16::from_be_bytes(buffer),
+			Endianness::Little => u16::from_le_bytes(buffer),
+		})
+	}
+
+	fn u32(&self, offset: usize) -> Result<u32> {
+		let mut buffer = [0; 4];
+		buffer.copy_from_slice(self.bytes(offset, 4)?);
+		Ok(match self.endianness {
+			Endianness::Big => u32::from_be_bytes(buffer),
+			Endianness::Little => u32::from_le_bytes(buffer),
+		})
+	}
+
+	/// Reads a NUL-terminated string
+	fn string(&self, offset: usize) -> Result<&'a str> {
+		let bytes = self.data.get(offset..).ok_or_else(truncated)?;
+		let end = bytes.iter().position(|&byte| byte == 0).ok_or_else(truncated)?;
+		std::str::from_utf8(&bytes[..end]).map_err(|_| Error::InvalidFormat("invalid string in ELF file".to_string()))
+	}
+}
+
+fn truncated() -> Error {
+	Error::InvalidFormat("truncated ELF file".to_string())
+}
+
+
+
+/// Section header fields used by the loader
+struct SectionHeader {
+	name: usize,
+	kind: u32,
+	address: usize,
+	offset: usize,
+	size: usize,
+	link: usize,
+	entry_size: usize,
+}
+
+
+
+/// Loads an ELF32 file into a new workspace. The architecture is selected from
+/// `e_machine`, `PT_LOAD` segments become sections and the symbol table is imported.
+pub fn load(data: &[u8]) -> Result<Workspace> {
+	if !is_elf(data) {
+		return Err(Error::InvalidFormat("not an ELF file".to_string()));
+	}
+
+	let mut reader = Reader {data, endianness: Endianness::Big};
+	if reader.u8(4)? != CLASS_32 {
+		return Err(Error::InvalidFormat("only 32-bit ELF files are supported".to_string()));
+	}
+	reader.endianness = match reader.u8(5)? {
+		DATA_LSB => Endianness::Little,
+		DATA_MSB => Endianness::Big,
+		other => return Err(Error::InvalidFormat(format!("invalid ELF data encoding {}", other))),
+	};
+
+	let arch = match reader.u16(18)? {
+		MACHINE_SH => architecture::from_name("sh2e"),
+		_ => None,
+	};
+	let arch = arch.ok_or_else(|| Error::InvalidFormat(format!("unsupported ELF machine {}", reader.u16(18).unwrap_or(0))))?;
+
+	let mut ws = Workspace::new(arch);
+	ws.memory.set_endianness(reader.endianness);
+	ws.entry_points.push(reader.u32(24)? as usize);
+
+	let sections = section_headers(&reader)?;
+	// Without a section name table the sections stay unnamed
+	let section_names = match reader.u16(50)? {
+		SHN_UNDEF => None,
+		index => sections.get(index as usize).map(|strtab| strtab.offset),
+	};
+
+	// Program headers
+	let ph_offset = reader.u32(28)? as usize;
+	let ph_size = reader.u16(42)? as usize;
+	for i in 0..reader.u16(44)? as usize {
+		let header = ph_offset + i * ph_size;
+		if reader.u32(header)? != PT_LOAD {
+			continue;
+		}
+
+		let offset = reader.u32(header + 4)? as usize;
+		let address = reader.u32(header + 8)? as usize;
+		let file_size = reader.u32(header + 16)? as usize;
+		let memory_size = reader.u32(header + 20)? as usize;
+		let flags = reader.u32(header + 24)?;
+		if memory_size == 0 {
+			continue;
+		}
+		if file_size > memory_size || memory_size > MAX_SECTION_SIZE {
+			return Err(Error::InvalidFormat(format!("invalid segment size 0x{:X} at 0x{:X}", memory_size, address)));
+		}
+
+		// Memory beyond the file contents (.bss) is zeroed
+		let mut contents = reader.bytes(offset, file_size)?.to_vec();
+		contents.resize(memory_size, 0);
+
+		let mut section_flags = if flags & PF_X != 0 {
+			SectionFlags::Execute | SectionFlags::Code
+		} else {
+			SectionFlags::Data
+		};
+		if flags & PF_R != 0 {
+			section_flags |= SectionFlags::Read;
+		}
+		if flags & PF_W != 0 {
+			section_flags |= SectionFlags::Write;
+		}
+
+		// Name the segment after the first section it starts with
+		let mut section = Section::from_raw(address, contents, section_flags);
+		if let Some(names) = section_names {
+			if let Some(header) = sections.iter().find(|header| header.address == address && header.size != 0) {
+				section = section.with_name(reader.string(names + header.name)?);
+			}
+		}
+		ws.memory.add_section(section)?;
+	}
+
+	// Symbols
+	for symtab in sections.iter().filter(|header| header.kind == SHT_SYMTAB && header.entry_size != 0) {
+		let strtab = sections.get(symtab.link).ok_or_else(|| Error::InvalidFormat("invalid symbol string table".to_string()))?;
+		for i in 0..symtab.size / symtab.entry_size {
+			let symbol = symtab.offset + i * symtab.entry_size;
+			let name = reader.string(strtab.offset + reader.u32(symbol)? as usize)?;
+			let value = reader.u32(symbol + 4)? as usize;
+			let info = reader.u8(symbol + 12)?;
+			let index = reader.u16(symbol + 14)?;
+
+			if name.is_empty() || index == SHN_UNDEF {
+				continue;
+			}
+			if let STT_NOTYPE | STT_OBJECT | STT_FUNC = info & 0xF {
+				match ws.symbols.insert(value, name, SymbolSource::Imported) {
+					// Local symbols of different files may share a name; later ones are qualified by their address
+					Err(Error::DuplicateSymbol(_)) if info >> 4 == STB_LOCAL => {
+						ws.symbols.insert(value, &format!("{}_{:X}", name, value), SymbolSource::Imported)?;
+					}
+					result => {
+						result?;
+					}
+				}
+			}
+		}
+	}
+
+	Ok(ws)
+}
+
+
+
+fn section_headers(reader: &Reader) -> Result<Vec<SectionHeader>> {
+	let offset = reader.u32(32)? as usize;
+	let size = reader.u16(46)? as usize;
+	let mut headers = Vec::new();
+	if offset == 0 {
+		return Ok(headers);
+	}
+
+	for i in 0..reader.u16(48)? as usize {
+		let header = offset + i * size;
+		headers.push(SectionHeader {
+			name: reader.u32(header)? as usize,
+			kind: reader.u32(header + 4)?,
+			address: reader.u32(header + 12)? as usize,
+			offset: reader.u32(header + 16)? as usize,
+			size: reader.u32(header + 20)? as usize,
+			link: reader.u32(header + 24)? as usize,
+			entry_size: reader.u32(header + 36)? as usize,
+		});
+	}
+	Ok(headers)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Builds an SH ELF file with `.text` at 0x1000, `.data` at 0xFFFF8000 and the symbols
+	/// (name, value, st_info), all defined in `.text`
+	fn build(endianness: Endianness, shstrndx: u16, symbols: &[(&str, u32, u8)]) -> Vec<u8> {
+		let mut strtab = vec![0];
+		let mut symtab = vec![(0, 0, 0)];
+		for (name, value, info) in symbols {
+			symtab.push((strtab.len() as u32, *value, *info));
+			strtab.extend_from_slice(name.as_bytes());
+			strtab.push(0);
+		}
+		let shstrtab = b"\0.text\0.data\0.symtab\0.strtab\0.shstrtab\0";
+		let data = [1, 2, 3, 4];
+
+		let symtab_offset = 52 + 2 * 32 + 8 + data.len();
+		let strtab_offset = symtab_offset + symtab.len() * 16;
+		let shstrtab_offset = strtab_offset + strtab.len();
+		let sh_offset = (shstrtab_offset + shstrtab.len() + 3) & !3;
+
+		let mut out = b"\x7FELF".to_vec();
+		let u16 = |out: &mut Vec<u8>, value: u16| out.extend_from_slice(&match endianness {
+			Endianness::Big => value.to_be_bytes(),
+			Endianness::Little => value.to_le_bytes(),
+		});
+		let u32 = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&match endianness {
+			Endianness::Big => value.to_be_bytes(),
+			Endianness::Little => value.to_le_bytes(),
+		});
+
+		out.extend_from_slice(&[CLASS_32, if endianness == Endianness::Big { DATA_MSB } else { DATA_LSB }, 1]);
+		out.resize(16, 0);
+		u16(&mut out, 2);
+		u16(&mut out, MACHINE_SH);
+		for value in &[1, 0x1000, 52, sh_offset as u32, 0] {
+			u32(&mut out, *value);
+		}
+		for value in &[52, 32, 2, 40, 6, shstrndx] {
+			u16(&mut out, *value);
+		}
+
+		// Program headers
+		for header in &[[PT_LOAD, 116, 0x1000, 0x1000, 8, 8, PF_R | PF_X, 2], [PT_LOAD, 124, 0xFFFF8000, 0xFFFF8000, 4, 16, PF_R | PF_W, 4]] {
+			for value in header {
+				u32(&mut out, *value);
+			}
+		}
+		// sts.l PR, @-R15 ; rts ; lds.l @R15+, PR (slot)
+		for word in &[0x4F22, 0x000B, 0x4F26, 0x0009] {
+			u16(&mut out, *word);
+		}
+		out.extend_from_slice(&data);
+		for (name, value, info) in symtab {
+			u32(&mut out, name);
+			u32(&mut out, value);
+			u32(&mut out, 0);
+			out.extend_from_slice(&[info, 0]);
+			u16(&mut out, if name == 0 { SHN_UNDEF } else { 1 });
+		}
+		out.extend_from_slice(&strtab);
+		out.extend_from_slice(shstrtab);
+		out.resize(sh_offset, 0);
+
+		// Section headers: name, type, flags, address, offset, size, link, info, alignment, entry size
+		let headers = [
+			[0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+			[1, 1, 6, 0x1000, 116, 8, 0, 0, 2, 0],
+			[7, 1, 3, 0xFFFF8000, 124, 4, 0, 0, 4, 0],
+			[13, SHT_SYMTAB, 0, 0, symtab_offset as u32, (strtab_offset - symtab_offset) as u32, 4, 1, 4, 16],
+			[21, 3, 0, 0, strtab_offset as u32, strtab.len() as u32, 0, 0, 1, 0],
+			[29, 3, 0, 0, shstrtab_offset as u32, shstrtab.len() as u32, 0, 0, 1, 0],
+		];
+		for header in &headers {
+			for value in header {
+				u32(&mut out, *value);
+			}
+		}
+		out
+	}
+
+	#[test]
+	fn segments_and_symbols() {
+		const GLOBAL_FUNC: u8 = 0x10 | STT_FUNC;
+		const GLOBAL_OBJECT: u8 = 0x10 | STT_OBJECT;
+		for endianness in &[Endianness::Big, Endianness::Little] {
+			let ws = load(&build(*endianness, 5, &[("main", 0x1000, GLOBAL_FUNC), ("counter", 0xFFFF8000, GLOBAL_OBJECT)])).unwrap();
+			assert_eq!(ws.memory.endianness(), *endianness);
+			let sections: Vec<_> = ws.memory.sections().map(|section| (section.address(), section.len(), section.flags(), section.name())).collect();
+			assert_eq!(sections, vec![
+				(0x1000, 8, SectionFlags::Read | SectionFlags::Execute | SectionFlags::Code, Some(".text")),
+				(0xFFFF8000, 16, SectionFlags::ReadWrite | SectionFlags::Data, Some(".data")),
+			]);
+			assert_eq!(ws.memory.read_u8(0xFFFF8003).unwrap(), 4);
+			assert_eq!(ws.memory.read_u8(0xFFFF8004).unwrap(), 0);
+			assert_eq!(ws.symbols.name(0x1000), Some("main"));
+			assert_eq!(ws.symbols.name(0xFFFF8000), Some("counter"));
+			assert_eq!(ws.entry_points, vec![0x1000]);
+			assert_eq!(ws.arch.disassemble_single(&ws.memory, 0x1000).unwrap().0.to_string(), "sts.l PR, @-R15");
+		}
+	}
+
+	#[test]
+	fn missing_section_names() {
+		for shstrndx in &[SHN_UNDEF, 99] {
+			let ws = load(&build(Endianness::Big, *shstrndx, &[])).unwrap();
+			assert!(ws.memory.sections().all(|section| section.name().is_none()));
+		}
+	}
+
+	#[test]
+	fn duplicate_symbols() {
+		// Local symbols of different files can share a name
+		let ws = load(&build(Endianness::Big, 5, &[("helper", 0x1000, STT_FUNC), ("helper", 0x1004, STT_FUNC)])).unwrap();
+		assert_eq!(ws.symbols.name(0x1000), Some("helper"));
+		assert_eq!(ws.symbols.name(0x1004), Some("helper_1004"));
+
+		let global = 0x10 | STT_FUNC;
+		let result = load(&build(Endianness::Big, 5, &[("helper", 0x1000, global), ("helper", 0x1004, global)]));
+		assert!(matches!(result, Err(Error::DuplicateSymbol(_))));
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(load(b"\x7FELF\x01").is_err());
+		assert!(load(b"\x7FELF\x02\x02\x01").is_err());
+		assert!(load(b"MZ").is_err());
+
+		// Segment sizes of the .data program header: more file contents than memory, and a 4 GiB segment
+		for (file_size, memory_size) in &[(32u32, 16u32), (4, 0xFFFF_FFFF)] {
+			let mut data = build(Endianness::Big, 5, &[]);
+			data[100..104].copy_from_slice(&file_size.to_be_bytes());
+			data[104..108].copy_from_slice(&memory_size.to_be_bytes());
+			assert!(matches!(load(&data), Err(Error::InvalidFormat(_))));
+		}
+	}
+}
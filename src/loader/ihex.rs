@@ -0,0 +1,95 @@
+use crate::error::{Error, Result};
+use crate::loader::{parse_hex, Records};
+
+
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+
+
+/// Parses Intel HEX records, including extended segment and linear addresses.
+/// Parsing stops at the end of file record.
+pub fn parse(text: &str) -> Result<Records> {
+	let mut records = Records::default();
+	let mut base = 0;
+
+	for (i, line) in text.lines().enumerate() {
+		let number = i + 1;
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		if !line.starts_with(':') {
+			return Err(Error::InvalidRecord(number, "expected ':'".to_string()));
+		}
+		let bytes = parse_hex(&line[1..], number)?;
+
+		// Length, 16-bit address, type, data and checksum
+		if bytes.len() < 5 || bytes[0] as usize != bytes.len() - 5 {
+			return Err(Error::InvalidRecord(number, "length does not match the byte count".to_string()));
+		}
+
+		// All bytes including the checksum sum to zero
+		if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+			return Err(Error::InvalidRecord(number, "checksum mismatch".to_string()));
+		}
+
+		let offset = (bytes[1] as usize) << 8 | bytes[2] as usize;
+		let data = &bytes[4..bytes.len() - 1];
+		let value = || data.iter().fold(0, |value, byte| (value << 8) | *byte as usize);
+
+		match bytes[3] {
+			DATA => records.data.push((base + offset, data.to_vec())),
+			END_OF_FILE => break,
+			EXTENDED_SEGMENT_ADDRESS if data.len() == 2 => base = value() << 4,
+			EXTENDED_LINEAR_ADDRESS if data.len() == 2 => base = value() << 16,
+			START_SEGMENT_ADDRESS if data.len() == 4 => {
+				// CS:IP
+				let value = value();
+				records.entry = Some(((value >> 16) << 4) + (value & 0xFFFF));
+			}
+			START_LINEAR_ADDRESS if data.len() == 4 => records.entry = Some(value()),
+			EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS | START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {
+				return Err(Error::InvalidRecord(number, "invalid address record length".to_string()));
+			}
+			kind => return Err(Error::InvalidRecord(number, format!("unsupported record type {:02X}", kind))),
+		}
+	}
+
+	Ok(records)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn records() {
+		let records = parse(":0400000512345678E3\n:10010000214601360121470136007EFE09D2190140\n:020000040001F9\n:02000000AABB99\n:020000021000EC\n:01000100CC32\n:00000001FF\n:01000000DD22\n").unwrap();
+		assert_eq!(records.entry, Some(0x12345678));
+		assert_eq!(records.data, vec![
+			(0x100, vec![0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01, 0x36, 0x00, 0x7E, 0xFE, 0x09, 0xD2, 0x19, 0x01]),
+			(0x10000, vec![0xAA, 0xBB]),
+			(0x10001, vec![0xCC]),
+		]);
+	}
+
+	#[test]
+	fn malformed() {
+		let error = |text| parse(text).err().unwrap().to_string();
+		assert_eq!(error(":0B0010006164647265737320676170A7\n:xx"), "line 2: invalid hex digit");
+		assert_eq!(error(":0100000000FE"), "line 1: checksum mismatch");
+		assert_eq!(error(":0200000000FE"), "line 1: length does not match the byte count");
+		assert_eq!(error("0100000000FF"), "line 1: expected ':'");
+		assert_eq!(error(":0300000400000FA"), "line 1: odd number of hex digits");
+		assert_eq!(error(":03000004000001F8"), "line 1: invalid address record length");
+	}
+}
@@ -0,0 +1,86 @@
+use crate::error::{Error, Result};
+use crate::loader::{parse_hex, Records};
+
+
+
+/// Parses Motorola S-records. S1-S3 data records are collected, S7-S9 give the
+/// entry point and S0/S5/S6 are checked but otherwise ignored.
+pub fn parse(text: &str) -> Result<Records> {
+	let mut records = Records::default();
+
+	for (i, line) in text.lines().enumerate() {
+		let number = i + 1;
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		if !line.starts_with('S') || line.len() < 4 || !line.is_ascii() {
+			return Err(Error::InvalidRecord(number, "expected an S-record".to_string()));
+		}
+		let kind = line.as_bytes()[1];
+		let bytes = parse_hex(&line[2..], number)?;
+
+		// The count covers the address, data and checksum
+		if bytes[0] as usize != bytes.len() - 1 {
+			return Err(Error::InvalidRecord(number, "length does not match the byte count".to_string()));
+		}
+
+		// The checksum is the ones' complement of the sum of the other bytes
+		let (checksum, body) = bytes.split_last().unwrap();
+		let sum = body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+		if !sum != *checksum {
+			return Err(Error::InvalidRecord(number, "checksum mismatch".to_string()));
+		}
+
+		let address_size = match kind {
+			b'0' | b'1' | b'5' | b'9' => 2,
+			b'2' | b'6' | b'8' => 3,
+			b'3' | b'7' => 4,
+			_ => return Err(Error::InvalidRecord(number, format!("unsupported record type S{}", kind as char))),
+		};
+		if body.len() < 1 + address_size {
+			return Err(Error::InvalidRecord(number, "record is too short".to_string()));
+		}
+
+		let address = body[1..=address_size].iter().fold(0, |address, byte| (address << 8) | *byte as usize);
+		let data = &body[1 + address_size..];
+
+		match kind {
+			b'1' | b'2' | b'3' => records.data.push((address, data.to_vec())),
+			b'7' | b'8' | b'9' => records.entry = Some(address),
+			_ => {},
+		}
+	}
+
+	Ok(records)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn records() {
+		let records = parse("S00600004844521B\nS1130000285F245F2212226A000424290008237C2A\nS2080100000102030AE6\nS9030000FC\n").unwrap();
+		assert_eq!(records.data, vec![
+			(0x0000, vec![0x28, 0x5F, 0x24, 0x5F, 0x22, 0x12, 0x22, 0x6A, 0x00, 0x04, 0x24, 0x29, 0x00, 0x08, 0x23, 0x7C]),
+			(0x10000, vec![0x01, 0x02, 0x03, 0x0A]),
+		]);
+		assert_eq!(records.entry, Some(0));
+	}
+
+	#[test]
+	fn malformed() {
+		let error = |text| parse(text).err().unwrap().to_string();
+		assert_eq!(error("S00600004844521B\nS1130000285F245F2212226A000424290008237C2B\n"), "line 2: checksum mismatch");
+		assert_eq!(error("S1130000285F"), "line 1: length does not match the byte count");
+		assert_eq!(error("X1030000FC"), "line 1: expected an S-record");
+		assert_eq!(error("S4030000FC"), "line 1: unsupported record type S4");
+		assert_eq!(error("S1030G00FC"), "line 1: invalid hex digit");
+		assert_eq!(error("S\u{e9}00"), "line 1: expected an S-record");
+		assert_eq!(error("S10\u{e9}00"), "line 1: expected an S-record");
+	}
+}
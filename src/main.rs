@@ -1,15 +1,471 @@
-pub mod architecture;
-pub mod error;
-pub mod memory;
-pub mod workspace;
-pub mod il;
+use beaglere::architecture::Operand;
+use beaglere::error::{Error, Result};
+use beaglere::export;
+use beaglere::loader::{self, Format, LoadOptions};
+use beaglere::memory::{Section, SectionFlags};
+use beaglere::workspace::Workspace;
+use beaglere::xref::XrefKind;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "usage: beaglere [options] <file> <action>
+
+<file> is an ELF, Motorola S-record, Intel HEX or raw binary file
+
+options:
+    --arch <name>                          architecture (default: from the file, or sh2e)
+    --base <address>                       load address of raw images (default: 0)
+    --entry <address>                      add an entry point for analysis, may be repeated
+    --vbr <address>                        address of the exception vector table (default: 0)
+    --name <address>:<name>                name an address, may be repeated
+    --format <raw|elf|srec|ihex>           file format (default: detected from the contents)
+    --fill <byte>                          fill byte for gaps in raw exports (default: 0xFF)
+    --record-width <bytes>                 data bytes per exported record (default: 16)
+    --annotate                             analyze before disasm and show the addresses accessed through registers
+    --section <name>:<address>:<size>[:<rwx>]
+                                           map an additional zeroed section of up to 256 MiB, e.g. RAM or I/O
+                                           (default flags: rw, not allowed with export)
+
+actions:
+    disasm <address> [count]               disassemble count instructions (default: 32)
+    il <address> [count]                   show the IL of count instructions (default: 32)
+    analyze                                disassemble everything reachable from the entry points and vectors
+    vectors                                list the exception vector table
+    functions                              list the functions found by analysis
+    cfg <address>                          show the control flow graph, dominators and loops of a function
+    ssa <address>                          show a function in static single assignment form
+    xrefs <address>                        list the references to and from an address
+    symbols                                list the named addresses after analysis
+    export <raw|srec|ihex> <output>        write the loaded image";
+
+
+
+enum Action {
+	Disassemble(usize, usize),
+	Lift(usize, usize),
+	Analyze,
+	Vectors,
+	Functions,
+	Cfg(usize),
+	Ssa(usize),
+	Xrefs(usize),
+	Symbols,
+	Export(String, PathBuf),
+}
+
+
+
+struct Options {
+	load: LoadOptions,
+	fill: u8,
+	record_width: usize,
+	sections: Vec<Section>,
+	entry_points: Vec<usize>,
+	names: Vec<(usize, String)>,
+	vbr: usize,
+	annotate: bool,
+	file: PathBuf,
+	action: Action,
+}
+
+
+
+/// Parses a decimal or 0x-prefixed hexadecimal number
+fn parse_number(text: &str) -> Result<usize> {
+	let result = if text.starts_with("0x") || text.starts_with("0X") {
+		usize::from_str_radix(&text[2..], 16)
+	} else {
+		text.parse()
+	};
+	result.map_err(|_| Error::InvalidArgument(format!("invalid number '{}'", text)))
+}
+
+
+
+/// Parses a `name:address:size[:rwx]` section description
+fn parse_section(text: &str) -> Result<Section> {
+	let parts: Vec<&str> = text.split(':').collect();
+	if parts.len() < 3 || parts.len() > 4 {
+		return Err(Error::InvalidArgument(format!("invalid section '{}'", text)));
+	}
+
+	let mut flags = SectionFlags::Data;
+	for c in parts.get(3).unwrap_or(&"rw").chars() {
+		flags |= match c {
+			'r' => SectionFlags::Read,
+			'w' => SectionFlags::Write,
+			'x' => SectionFlags::Execute | SectionFlags::Code,
+			_ => return Err(Error::InvalidArgument(format!("invalid section flag '{}'", c))),
+		};
+	}
+
+	let address = parse_number(parts[1])?;
+	let size = parse_number(parts[2])?;
+	if size == 0 || size > loader::MAX_SECTION_SIZE || address.checked_add(size).is_none() {
+		return Err(Error::InvalidArgument(format!("invalid section size 0x{:X} at 0x{:X}", size, address)));
+	}
+	Ok(Section::from_raw(address, vec![0; size], flags).with_name(parts[0]))
+}
+
+
+
+/// Parses an `address:name` pair
+fn parse_name(text: &str) -> Result<(usize, String)> {
+	match text.split_once(':') {
+		Some((address, name)) => Ok((parse_number(address)?, name.to_string())),
+		None => Err(Error::InvalidArgument(format!("invalid name '{}'", text))),
+	}
+}
+
+
+
+fn parse_args(args: &[String]) -> Result<Options> {
+	let mut load = LoadOptions::default();
+	let mut fill = 0xFF;
+	let mut record_width = 16;
+	let mut sections = Vec::new();
+	let mut entry_points = Vec::new();
+	let mut names = Vec::new();
+	let mut vbr = 0;
+	let mut annotate = false;
+	let mut positional = Vec::new();
+
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		let mut value = || iter.next().ok_or_else(|| Error::InvalidArgument(format!("missing value for {}", arg)));
+		match arg.as_str() {
+			"--arch" => load.arch = Some(value()?.clone()),
+			"--base" => load.base = parse_number(value()?)?,
+			"--entry" => entry_points.push(parse_number(value()?)?),
+			"--vbr" => vbr = parse_number(value()?)?,
+			"--name" => names.push(parse_name(value()?)?),
+			"--format" => {
+				let name = value()?;
+				load.format = Some(Format::from_name(name).ok_or_else(|| Error::InvalidArgument(format!("unknown format '{}'", name)))?);
+			}
+			"--fill" => {
+				let value = parse_number(value()?)?;
+				fill = if value <= 0xFF { value as u8 } else { return Err(Error::InvalidArgument(format!("invalid fill byte {}", value))) };
+			}
+			"--record-width" => record_width = parse_number(value()?)?,
+			"--section" => sections.push(parse_section(value()?)?),
+			"--annotate" => annotate = true,
+			_ if arg.starts_with("--") => return Err(Error::InvalidArgument(format!("unknown option {}", arg))),
+			_ => positional.push(arg.as_str()),
+		}
+	}
+
+	let action = match positional.get(1..) {
+		Some(["disasm", address]) => Action::Disassemble(parse_number(address)?, 32),
+		Some(["disasm", address, count]) => Action::Disassemble(parse_number(address)?, parse_number(count)?),
+		Some(["il", address]) => Action::Lift(parse_number(address)?, 32),
+		Some(["il", address, count]) => Action::Lift(parse_number(address)?, parse_number(count)?),
+		Some(["analyze"]) => Action::Analyze,
+		Some(["vectors"]) => Action::Vectors,
+		Some(["functions"]) => Action::Functions,
+		Some(["cfg", address]) => Action::Cfg(parse_number(address)?),
+		Some(["ssa", address]) => Action::Ssa(parse_number(address)?),
+		Some(["xrefs", address]) => Action::Xrefs(parse_number(address)?),
+		Some(["symbols"]) => Action::Symbols,
+		Some(["export", format, output]) => Action::Export(format.to_string(), PathBuf::from(output)),
+		_ => return Err(Error::InvalidArgument(USAGE.to_string())),
+	};
+	// Additional sections describe the target's memory map and are not part of an exported image
+	if matches!(action, Action::Export(..)) && !sections.is_empty() {
+		return Err(Error::InvalidArgument("--section cannot be used with export".to_string()));
+	}
+
+	Ok(Options {
+		load,
+		fill,
+		record_width,
+		sections,
+		entry_points,
+		names,
+		vbr,
+		annotate,
+		file: PathBuf::from(positional[0]),
+		action,
+	})
+}
+
+
+
+fn disassemble(ws: &Workspace, start: usize, count: usize) {
+	let mut address = start;
+	for _ in 0..count {
+		match ws.arch.disassemble_single(&ws.memory, address) {
+			Ok((instruction, size)) => {
+				if let Some(name) = ws.symbols.name(address) {
+					println!("{:08X} {}:", address, name);
+				}
+				// Addresses accessed through registers, which the operands do not show. Only known after analysis
+				let mut targets: Vec<usize> = Vec::new();
+				let indirect = instruction.operands().iter().any(|operand| match operand {
+					Operand::Memory(memory) => memory.base.is_some() || memory.index.is_some(),
+					_ => false,
+				});
+				if indirect {
+					for xref in ws.xrefs.from(address) {
+						if matches!(xref.kind, XrefKind::Read | XrefKind::Write) && !targets.contains(&xref.to) {
+							targets.push(xref.to);
+						}
+					}
+				}
+
+				let mut line = format!("{:08X}    {}", address, instruction.display(&ws.symbols));
+				for (i, to) in targets.iter().enumerate() {
+					line.push_str(if i == 0 { " ; " } else { ", " });
+					match ws.symbols.name(*to) {
+						Some(name) => line.push_str(&format!("@{}", name)),
+						None => line.push_str(&format!("@0x{:X}", to)),
+					}
+				}
+				println!("{}", line);
+				address += size;
+			}
+			Err(Error::InvalidInstruction) => {
+				// Not decodable; show the raw data and continue at the next word
+				let word = ws.memory.read_u16(address).unwrap_or(0);
+				println!("{:08X}    .word 0x{:04X}", address, word);
+				address += 2;
+			}
+			Err(err) => {
+				println!("{:08X}    {}", address, err);
+				break;
+			}
+		}
+	}
+}
+
+
+
+fn lift(ws: &Workspace, start: usize, count: usize) {
+	let mut address = start;
+	for _ in 0..count {
+		match ws.arch.disassemble_single(&ws.memory, address) {
+			Ok((instruction, size)) => {
+				println!("{:08X}    {}", address, instruction.display(&ws.symbols));
+				for tree in ws.arch.lift(&instruction, address) {
+					match tree.validate() {
+						Ok(()) => println!("                {}", tree),
+						Err(err) => println!("                {} ; {}", tree, err),
+					}
+				}
+				address += size;
+			}
+			Err(Error::InvalidInstruction) => {
+				let word = ws.memory.read_u16(address).unwrap_or(0);
+				println!("{:08X}    .word 0x{:04X}", address, word);
+				address += 2;
+			}
+			Err(err) => {
+				println!("{:08X}    {}", address, err);
+				break;
+			}
+		}
+	}
+}
+
+
+
+fn analyze(ws: &Workspace) {
+	for section in ws.memory.sections().filter(|section| section.flags().contains(SectionFlags::Execute)) {
+		let mut decoded = 0;
+		let mut code = 0;
+		for (_, _, size) in ws.disassembly.instructions().filter(|(address, _, _)| *address >= section.address() && *address < section.end()) {
+			decoded += 1;
+			code += size;
+		}
+
+		println!("{} {:08X}-{:08X}: {} instructions, {} bytes of data",
+			section.name().unwrap_or("?"), section.address(), section.end(), decoded, section.len() - code);
+	}
+
+	for address in ws.disassembly.invalid() {
+		println!("{:08X}    unable to decode reached address", address);
+	}
+	for xref in ws.read_only_writes() {
+		println!("{:08X}    writes read-only memory at {:08X}", xref.from, xref.to);
+	}
+	println!("{} functions", ws.functions.len());
+}
+
+
+
+fn functions(ws: &Workspace) {
+	for function in ws.functions.iter() {
+		println!("{:08X}-{:08X}    {} blocks, {} returns    {}",
+			function.start(), function.end(), function.blocks().len(), function.returns().len(), function.name());
+	}
+}
+
+
+
+fn cfg(ws: &Workspace, function: usize) -> Result<()> {
+	let cfg = ws.cfg(function).ok_or_else(|| Error::InvalidArgument(format!("no function at 0x{:X}", function)))?;
+	let dominators = cfg.dominators();
+
+	for block in cfg.blocks() {
+		match dominators.immediate_dominator(block.start) {
+			Some(idom) => println!("{:08X}-{:08X}    idom {:08X}", block.start, block.end, idom),
+			None => println!("{:08X}-{:08X}", block.start, block.end),
+		}
+		for edge in cfg.successors(block.start) {
+			match edge.to {
+				Some(to) => println!("    -> {:08X} {:?}", to, edge.kind),
+				None => println!("    -> ? {:?}", edge.kind),
+			}
+		}
+	}
+
+	for found in cfg.loops() {
+		let blocks: Vec<String> = found.blocks.iter().map(|block| format!("{:08X}", block)).collect();
+		println!("loop {:08X}: {}", found.header, blocks.join(" "));
+	}
+	Ok(())
+}
+
+
+
+fn ssa(ws: &Workspace, function: usize) -> Result<()> {
+	let ssa = ws.ssa(function).ok_or_else(|| Error::InvalidArgument(format!("no function at 0x{:X}", function)))?;
+	for block in ssa.blocks() {
+		println!("{:08X}-{:08X}", block.start, block.end);
+		for phi in &block.phis {
+			println!("                {}", phi);
+		}
+		for statement in &block.statements {
+			println!("{:08X}        {}", statement.address, statement);
+		}
+	}
+	Ok(())
+}
+
+
+
+fn xrefs(ws: &Workspace, address: usize) {
+	for xref in ws.xrefs.to(address) {
+		println!("{:08X} -> {:08X}    {:?}", xref.from, xref.to, xref.kind);
+	}
+	for xref in ws.xrefs.from(address) {
+		println!("{:08X} -> {:08X}    {:?}", xref.from, xref.to, xref.kind);
+	}
+}
+
+
+
+fn symbols(ws: &Workspace) {
+	for symbol in ws.symbols.iter() {
+		println!("{:08X}    {:?}    {}", symbol.address, symbol.source, symbol.name);
+	}
+}
+
+
+
+fn run(options: Options) -> Result<()> {
+	let mut ws = loader::load_file(&options.file, &options.load)?;
+
+	if let Action::Export(format, output) = &options.action {
+		// Additional sections describe the target's memory map and are not part of the image
+		let entry = ws.entry_points.first().cloned();
+		match format.as_str() {
+			"raw" => fs::write(output, export::raw(ws.memory.sections(), options.fill)?)?,
+			"srec" => fs::write(output, export::srec(ws.memory.sections(), options.record_width, entry)?)?,
+			"ihex" => fs::write(output, export::ihex(ws.memory.sections(), options.record_width, entry)?)?,
+			_ => return Err(Error::InvalidArgument(format!("unknown export format '{}'", format))),
+		}
+		return Ok(());
+	}
+
+	for section in options.sections {
+		ws.memory.add_section(section)?;
+	}
+	ws.entry_points.extend(options.entry_points);
+	for (address, name) in &options.names {
+		ws.rename(*address, name)?;
+	}
+
+	match options.action {
+		Action::Disassemble(start, count) if !options.annotate => disassemble(&ws, start, count),
+		Action::Lift(start, count) => lift(&ws, start, count),
+		Action::Vectors => {
+			for vector in ws.arch.vector_table(&ws.memory, options.vbr) {
+				println!("{:08X}    {:08X}    {}", vector.address, vector.value, vector.name);
+			}
+		}
+		action => {
+			ws.add_vector_table(options.vbr);
+			ws.analyze();
+			match action {
+				Action::Disassemble(start, count) => disassemble(&ws, start, count),
+				Action::Functions => functions(&ws),
+				Action::Cfg(function) => cfg(&ws, function)?,
+				Action::Ssa(function) => ssa(&ws, function)?,
+				Action::Xrefs(address) => xrefs(&ws, address),
+				Action::Symbols => symbols(&ws),
+				_ => analyze(&ws),
+			}
+		}
+	}
+	Ok(())
+}
+
+
 
 fn main() {
-    let mut ws = workspace::Workspace::new(Box::new(architecture::sh2e::SH2E::new()));
+	let args: Vec<String> = env::args().skip(1).collect();
+	if let Err(err) = parse_args(&args).and_then(run) {
+		eprintln!("{}", err);
+		process::exit(1);
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(text: &str) -> Vec<String> {
+		text.split_whitespace().map(String::from).collect()
+	}
 
-    ws.memory.add_section(memory::Section::from_raw(0, include_bytes!("60E0FB00.bin").to_vec()));
+	#[test]
+	fn sections() {
+		let section = parse_section("ram:0xFFFF8000:0x1000").unwrap();
+		assert_eq!((section.address(), section.len(), section.name()), (0xFFFF8000, 0x1000, Some("ram")));
+		assert_eq!(section.flags(), SectionFlags::ReadWrite | SectionFlags::Data);
+		assert_eq!(parse_section("io:0x100:4:r").unwrap().flags(), SectionFlags::Read | SectionFlags::Data);
+		assert!(parse_section("ram:0x0:0x1000:rz").is_err());
+		assert!(parse_section("ram:0x0").is_err());
+		assert!(parse_section("ram:0x0:0").is_err());
+		assert!(parse_section("ram:0x0:0xFFFFFFFF0").is_err());
+		assert!(parse_section(&format!("ram:0x{:X}:2", usize::MAX)).is_err());
+	}
 
-    let (instruction, size) = ws.arch.disassemble_single(&ws.memory, 0x836).unwrap();
+	#[test]
+	fn actions() {
+		let options = parse_args(&args("--base 0x1000 --section ram:0x0:16 rom.bin disasm 0x1000 4")).unwrap();
+		assert_eq!(options.load.base, 0x1000);
+		assert_eq!(options.sections.len(), 1);
+		assert!(matches!(options.action, Action::Disassemble(0x1000, 4)));
+		assert!(!options.annotate);
+		assert!(parse_args(&args("--annotate rom.bin disasm 0x1000")).unwrap().annotate);
+		assert!(matches!(parse_args(&args("rom.bin ssa 0x10")).unwrap().action, Action::Ssa(0x10)));
+		assert!(matches!(parse_args(&args("rom.bin analyze")).unwrap().action, Action::Analyze));
+		assert!(parse_args(&args("rom.bin")).is_err());
+		assert!(parse_args(&args("rom.bin disasm zero")).is_err());
+		assert!(parse_args(&args("--fill 0x100 rom.bin analyze")).is_err());
+		assert!(parse_args(&args("--bogus rom.bin analyze")).is_err());
+	}
 
-    println!("{}", instruction);
+	#[test]
+	fn export_rejects_sections() {
+		assert!(parse_args(&args("rom.bin export raw out.bin")).is_ok());
+		assert!(parse_args(&args("--section ram:0x0:16 rom.bin export raw out.bin")).is_err());
+	}
 }
@@ -1,9 +1,58 @@
+// SectionFlags uses CamelCase flag names, which bitflags emits as associated constants
+#![allow(non_upper_case_globals)]
+
+use crate::error::{Error, Result};
 use bitflags::bitflags;
 use std::cmp;
+use std::collections::BTreeMap;
 
 /// Group of `[Section]`s
 pub struct Layout {
 	sections: Vec<Section>,
+	endianness: Endianness,
+	patches: Vec<Patch>,
+	undone: Vec<Patch>, // Patches that can be redone, most recent last
+}
+
+/// Modification of memory made through `Layout::write_memory`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+	pub address: usize,
+	pub original: Vec<u8>,
+	pub patched: Vec<u8>,
+}
+
+/// Byte order of multi-byte values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+	Big,
+	Little,
+}
+
+/// Generates an unsigned integer reader honoring the layout's endianness
+macro_rules! read_uint {
+	($name:ident, $ty:ty) => {
+		pub fn $name(&self, address: usize) -> Result<$ty> {
+			let mut buffer = [0; std::mem::size_of::<$ty>()];
+			self.read_exact(address, &mut buffer)?;
+			Ok(match self.endianness {
+				Endianness::Big => <$ty>::from_be_bytes(buffer),
+				Endianness::Little => <$ty>::from_le_bytes(buffer),
+			})
+		}
+	};
+}
+
+/// Generates an unsigned integer writer honoring the layout's endianness
+macro_rules! write_uint {
+	($name:ident, $ty:ty) => {
+		pub fn $name(&mut self, address: usize, value: $ty) -> Result<()> {
+			match self.endianness {
+				Endianness::Big => self.write_memory(address, &value.to_be_bytes()),
+				Endianness::Little => self.write_memory(address, &value.to_le_bytes()),
+			}
+		}
+	};
 }
 
 bitflags! {
@@ -25,6 +74,8 @@ bitflags! {
 pub struct Section {
 	address: usize,
 	memory: Vec<u8>,
+	flags: SectionFlags,
+	name: Option<String>,
 }
 
 impl Section {
@@ -32,48 +83,112 @@ impl Section {
 		self.memory.len()
 	}
 
-	pub fn from_raw(address: usize, raw: Vec<u8>) -> Section {
+	pub fn is_empty(&self) -> bool {
+		self.memory.is_empty()
+	}
+
+	pub fn from_raw(address: usize, raw: Vec<u8>, flags: SectionFlags) -> Section {
 		Section {
 			address,
 			memory: raw,
+			flags,
+			name: None,
 		}
 	}
+
+	pub fn with_name(mut self, name: &str) -> Section {
+		self.name = Some(name.to_string());
+		self
+	}
+
+	/// Returns the address of the first byte
+	pub fn address(&self) -> usize {
+		self.address
+	}
+
+	/// Returns the address after the last byte
+	pub fn end(&self) -> usize {
+		self.address + self.memory.len()
+	}
+
+	pub fn flags(&self) -> SectionFlags {
+		self.flags
+	}
+
+	pub fn name(&self) -> Option<&str> {
+		self.name.as_deref()
+	}
+
+	pub fn data(&self) -> &[u8] {
+		&self.memory
+	}
+}
+
+impl Default for Layout {
+	fn default() -> Layout {
+		Layout::new()
+	}
 }
 
 impl Layout {
+	/// Creates an empty big-endian layout
 	pub fn new() -> Layout {
 		Layout {
 			sections: Vec::new(),
+			endianness: Endianness::Big,
+			patches: Vec::new(),
+			undone: Vec::new(),
 		}
 	}
 
-	pub fn add_section(&mut self, section: Section) -> &mut Section {
-		let offset = section.address;
-		// Insert the section in order
-		for (i, entry) in self.sections.iter().enumerate() {
-			if entry.address > offset {
-				self.sections.insert(i, section);
-				return self.sections.get_mut(i).unwrap();
-			}
+	pub fn endianness(&self) -> Endianness {
+		self.endianness
+	}
+
+	pub fn set_endianness(&mut self, endianness: Endianness) {
+		self.endianness = endianness;
+	}
+
+	/// Inserts the section in order. Fails if it overlaps an existing section
+	pub fn add_section(&mut self, section: Section) -> Result<&mut Section> {
+		// Index of the first section starting after the new one
+		let i = self.sections.partition_point(|entry| entry.address <= section.address);
+
+		if i > 0 && self.sections[i - 1].end() > section.address {
+			return Err(Error::OverlappingSection);
+		}
+		if i < self.sections.len() && self.sections[i].address < section.end() {
+			return Err(Error::OverlappingSection);
+		}
+
+		self.sections.insert(i, section);
+		Ok(&mut self.sections[i])
+	}
+
+	/// Returns the index of the section containing the address
+	fn section_index(&self, address: usize) -> Option<usize> {
+		// Sections are ordered and do not overlap; the only candidate is the last one starting at or before the address
+		let i = self.sections.partition_point(|section| section.address <= address).checked_sub(1)?;
+		if address < self.sections[i].end() {
+			Some(i)
+		} else {
+			None
 		}
-		// All entries were less than offset or the array is empty; append to the end
-		self.sections.push(section);
-		self.sections.last_mut().unwrap()
 	}
 
-	/// Reads memory at the address into the buffer. Returns the amount written to the buffer
-	pub fn read_memory(&self, start_address: usize, start_buffer: &mut [u8]) -> usize {
+	/// Reads memory at the address into the buffer. Returns the amount written to the buffer,
+	/// which is less than the buffer size if the read reaches unmapped memory.
+	/// Fails if the address is not mapped.
+	pub fn read_memory(&self, start_address: usize, start_buffer: &mut [u8]) -> Result<usize> {
 		// Find the section containing the address
-		let start_section = self.sections.iter().enumerate().find(|(_, section)| section.address <= start_address);
-		if start_section.is_none() {
-			return 0;
-		}
-		let (mut section_id, mut section) = start_section.unwrap();
+		let mut section_id = self.section_index(start_address).ok_or(Error::InvalidMemory)?;
 
 		let mut buffer = start_buffer;
 		let mut address = start_address;
 
 		while !buffer.is_empty() {
+			let section = &self.sections[section_id];
+
 			// Find the offset within the section
 			let offset = address - section.address;
 			let to_read = cmp::min(section.memory.len() - offset, buffer.len());
@@ -83,25 +198,337 @@ impl Layout {
 			buffer = &mut buffer[to_read..];
 			address += to_read;
 
-			// Get new section
-			let section_opt = &self.sections[section_id..].iter().enumerate().find(|(_, section)| section.address <= address);
-			if section_opt.is_none() {
-				break;
+			// Continue into the next section only if it is adjacent
+			section_id += 1;
+			match self.sections.get(section_id) {
+				Some(next) if next.address == address => {},
+				_ => break,
+			}
+		}
+
+		Ok(address - start_address)
+	}
+
+	/// Fills the whole buffer from memory. Fails if any byte is not mapped
+	pub fn read_exact(&self, address: usize, buffer: &mut [u8]) -> Result<()> {
+		if self.read_memory(address, buffer)? < buffer.len() {
+			return Err(Error::InvalidMemory);
+		}
+		Ok(())
+	}
+
+	read_uint!(read_u8, u8);
+	read_uint!(read_u16, u16);
+	read_uint!(read_u32, u32);
+	read_uint!(read_u64, u64);
+
+	pub fn read_i8(&self, address: usize) -> Result<i8> {
+		Ok(self.read_u8(address)? as i8)
+	}
+
+	pub fn read_i16(&self, address: usize) -> Result<i16> {
+		Ok(self.read_u16(address)? as i16)
+	}
+
+	pub fn read_i32(&self, address: usize) -> Result<i32> {
+		Ok(self.read_u32(address)? as i32)
+	}
+
+	pub fn read_i64(&self, address: usize) -> Result<i64> {
+		Ok(self.read_u64(address)? as i64)
+	}
+
+	pub fn read_f32(&self, address: usize) -> Result<f32> {
+		Ok(f32::from_bits(self.read_u32(address)?))
+	}
+
+	pub fn read_f64(&self, address: usize) -> Result<f64> {
+		Ok(f64::from_bits(self.read_u64(address)?))
+	}
+
+	/// Writes the data into memory and records it in the patch log. Clears the redo history.
+	/// Read-only sections can be patched; use `write_memory_checked` to enforce section permissions.
+	/// Fails without writing anything if any byte is not mapped.
+	pub fn write_memory(&mut self, address: usize, data: &[u8]) -> Result<()> {
+		let mut original = vec![0; data.len()];
+		self.read_exact(address, &mut original)?;
+
+		self.store(address, data);
+		self.patches.push(Patch {
+			address,
+			original,
+			patched: data.to_vec(),
+		});
+		self.undone.clear();
+		Ok(())
+	}
+
+	/// Writes the data like `write_memory`, but only into sections with the Write flag.
+	/// Fails with `Error::ReadOnly` without writing anything if any byte is read-only.
+	pub fn write_memory_checked(&mut self, address: usize, data: &[u8]) -> Result<()> {
+		let end = address.checked_add(data.len()).ok_or(Error::InvalidMemory)?;
+		let mut current = address;
+		while current < end {
+			let section = self.get_section_at(current)?;
+			if !section.flags.contains(SectionFlags::Write) {
+				return Err(Error::ReadOnly);
 			}
+			current = section.end();
+		}
+		self.write_memory(address, data)
+	}
 
-			let (section_id_l, section_l) = section_opt.unwrap();
-			section_id = section_id_l;
-			section = section_l;
+	/// Copies data into mapped memory without recording it
+	fn store(&mut self, start_address: usize, data: &[u8]) {
+		let mut address = start_address;
+		let mut remaining = data;
+
+		while !remaining.is_empty() {
+			let section = match self.section_index(address) {
+				Some(i) => &mut self.sections[i],
+				None => break,
+			};
+			let offset = address - section.address;
+			let to_write = cmp::min(section.memory.len() - offset, remaining.len());
+			section.memory[offset..offset + to_write].clone_from_slice(&remaining[..to_write]);
+
+			remaining = &remaining[to_write..];
+			address += to_write;
 		}
+	}
 
-		address - start_address
+	write_uint!(write_u8, u8);
+	write_uint!(write_u16, u16);
+	write_uint!(write_u32, u32);
+	write_uint!(write_u64, u64);
+
+	pub fn write_i8(&mut self, address: usize, value: i8) -> Result<()> {
+		self.write_u8(address, value as u8)
+	}
+
+	pub fn write_i16(&mut self, address: usize, value: i16) -> Result<()> {
+		self.write_u16(address, value as u16)
+	}
+
+	pub fn write_i32(&mut self, address: usize, value: i32) -> Result<()> {
+		self.write_u32(address, value as u32)
+	}
+
+	pub fn write_i64(&mut self, address: usize, value: i64) -> Result<()> {
+		self.write_u64(address, value as u64)
+	}
+
+	pub fn write_f32(&mut self, address: usize, value: f32) -> Result<()> {
+		self.write_u32(address, value.to_bits())
+	}
+
+	pub fn write_f64(&mut self, address: usize, value: f64) -> Result<()> {
+		self.write_u64(address, value.to_bits())
+	}
+
+	/// Returns the applied patches in the order they were made
+	pub fn patches(&self) -> &[Patch] {
+		&self.patches
+	}
+
+	/// Reverts the most recent patch. Returns false if there is nothing to undo
+	pub fn undo(&mut self) -> bool {
+		match self.patches.pop() {
+			Some(patch) => {
+				self.store(patch.address, &patch.original);
+				self.undone.push(patch);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Reapplies the most recently undone patch. Returns false if there is nothing to redo
+	pub fn redo(&mut self) -> bool {
+		match self.undone.pop() {
+			Some(patch) => {
+				self.store(patch.address, &patch.patched);
+				self.patches.push(patch);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Returns the contiguous ranges that differ from the unpatched image.
+	/// Bytes patched back to their original value are not included.
+	pub fn patched_ranges(&self) -> Vec<Patch> {
+		// The first patch touching an address holds its original value
+		let mut original = BTreeMap::new();
+		for patch in &self.patches {
+			for (i, byte) in patch.original.iter().enumerate() {
+				original.entry(patch.address + i).or_insert(*byte);
+			}
+		}
+
+		let mut ranges: Vec<Patch> = Vec::new();
+		for (address, original) in original {
+			let mut current = [0];
+			if self.read_exact(address, &mut current).is_err() || current[0] == original {
+				continue;
+			}
+
+			match ranges.last_mut() {
+				Some(range) if range.address + range.patched.len() == address => {
+					range.original.push(original);
+					range.patched.push(current[0]);
+				}
+				_ => ranges.push(Patch {
+					address,
+					original: vec![original],
+					patched: vec![current[0]],
+				}),
+			}
+		}
+		ranges
+	}
+
+	/// Returns the sections ordered by address
+	pub fn sections(&self) -> impl Iterator<Item = &Section> {
+		self.sections.iter()
+	}
+
+	/// Checks that `len` bytes at the address belong to a section with the
+	/// permissions in `access`. Reports non-executable code and writes to read-only memory.
+	#[allow(clippy::unnecessary_map_or)] // Option::is_none_or needs Rust 1.82
+	pub fn check_access(&self, address: usize, len: usize, access: SectionFlags) -> Result<()> {
+		let section = self.get_section_at(address)?;
+		if address.checked_add(len).map_or(true, |end| end > section.end()) {
+			return Err(Error::InvalidMemory);
+		}
+
+		if access.contains(SectionFlags::Execute) && !section.flags.contains(SectionFlags::Execute) {
+			return Err(Error::NotExecutable);
+		}
+		if access.contains(SectionFlags::Write) && !section.flags.contains(SectionFlags::Write) {
+			return Err(Error::ReadOnly);
+		}
+		if access.contains(SectionFlags::Read) && !section.flags.contains(SectionFlags::Read) {
+			return Err(Error::InvalidMemory);
+		}
+		Ok(())
 	}
 
 	/// Finds the section containing the address. If found, returns the section.
-	/// If not found, returns None.
-	pub fn get_section_at(&self, address: usize) -> Option<&Section> {
-		let section = self.sections.iter().find(|section| section.address <= address)?;
+	/// If the address is not mapped, returns `Error::InvalidMemory`.
+	pub fn get_section_at(&self, address: usize) -> Result<&Section> {
+		let i = self.section_index(address).ok_or(Error::InvalidMemory)?;
+		Ok(&self.sections[i])
+	}
+}
+
+/// Maps the big-endian words at 0x1000 as code, for decoding and analyzing them in tests
+#[cfg(test)]
+pub(crate) fn code_layout(words: &[u16]) -> Layout {
+	let bytes = words.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect();
+	let mut layout = Layout::new();
+	layout.add_section(Section::from_raw(0x1000, bytes, SectionFlags::Read | SectionFlags::Execute | SectionFlags::Code)).unwrap();
+	layout
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn section_flags_and_names() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x1000, vec![0, 9], SectionFlags::Read | SectionFlags::Data).with_name("ram")).unwrap();
+		let section = layout.sections().next().unwrap();
+		assert_eq!(section.name(), Some("ram"));
+		assert_eq!(section.flags(), SectionFlags::Read | SectionFlags::Data);
+		assert!(matches!(layout.check_access(0x1000, 2, SectionFlags::Execute), Err(Error::NotExecutable)));
+		assert!(matches!(layout.check_access(0x1000, 2, SectionFlags::Write), Err(Error::ReadOnly)));
+		assert!(matches!(layout.check_access(0x1001, 2, SectionFlags::Read), Err(Error::InvalidMemory)));
+		assert!(matches!(layout.check_access(0x1001, usize::MAX, SectionFlags::Read), Err(Error::InvalidMemory)));
+		assert!(layout.check_access(0x1000, 2, SectionFlags::Read).is_ok());
+	}
+
+	#[test]
+	fn section_lookup() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x1000, vec![1, 2, 3, 4], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x0, vec![9; 4], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x1004, vec![5, 6], SectionFlags::Read)).unwrap();
+		assert!(matches!(layout.add_section(Section::from_raw(0x1005, vec![0], SectionFlags::Read)), Err(Error::OverlappingSection)));
+		assert!(matches!(layout.add_section(Section::from_raw(0xFFF, vec![0, 0], SectionFlags::Read)), Err(Error::OverlappingSection)));
+		assert_eq!(layout.get_section_at(0x1003).unwrap().address(), 0x1000);
+		assert_eq!(layout.get_section_at(0x1004).unwrap().address(), 0x1004);
+		assert_eq!(layout.get_section_at(0x3).unwrap().address(), 0);
+		assert!(matches!(layout.get_section_at(0x4), Err(Error::InvalidMemory)));
+		assert!(matches!(layout.get_section_at(0x1006), Err(Error::InvalidMemory)));
+
+		// Reads continue into adjacent sections and stop at gaps
+		let mut buffer = [0; 8];
+		assert_eq!(layout.read_memory(0x1002, &mut buffer).unwrap(), 4);
+		assert_eq!(&buffer[..4], &[3, 4, 5, 6]);
+		assert_eq!(layout.read_memory(0x2, &mut buffer).unwrap(), 2);
+		assert!(layout.read_memory(0x10, &mut buffer).is_err());
+	}
+
+	#[test]
+	fn typed_accessors() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x10, vec![0x3F, 0x80, 0, 0, 0xFF, 0xFE, 1, 2, 3, 4, 5, 6], SectionFlags::Read)).unwrap();
+		assert_eq!(layout.endianness(), Endianness::Big);
+		assert_eq!(layout.read_f32(0x10).unwrap(), 1.0);
+		assert_eq!(layout.read_i16(0x14).unwrap(), -2);
+		assert_eq!(layout.read_u32(0x16).unwrap(), 0x01020304);
+		assert!(matches!(layout.read_u64(0x16), Err(Error::InvalidMemory)));
+		layout.set_endianness(Endianness::Little);
+		assert_eq!(layout.read_u16(0x16).unwrap(), 0x0201);
+		assert_eq!(layout.read_u64(0x14).unwrap(), 0x0605_0403_0201_FEFF);
+		assert_eq!(layout.read_i8(0x14).unwrap(), -1);
+	}
+
+	#[test]
+	fn checked_writes() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x10, vec![0; 4], SectionFlags::Read | SectionFlags::Execute)).unwrap();
+		layout.add_section(Section::from_raw(0x14, vec![0; 4], SectionFlags::ReadWrite)).unwrap();
+		layout.add_section(Section::from_raw(0x18, vec![0; 4], SectionFlags::ReadWrite)).unwrap();
+		assert!(matches!(layout.write_memory_checked(0x12, &[1, 2, 3, 4]), Err(Error::ReadOnly)));
+		assert!(matches!(layout.write_memory_checked(0x1A, &[1, 2, 3, 4]), Err(Error::InvalidMemory)));
+		assert!(layout.patches().is_empty());
+		layout.write_memory_checked(0x16, &[1, 2, 3, 4]).unwrap();
+		assert_eq!(layout.read_u32(0x16).unwrap(), 0x01020304);
+		// Patches are not restricted
+		layout.write_memory(0x10, &[5]).unwrap();
+		assert_eq!(layout.read_u8(0x10).unwrap(), 5);
+	}
+
+	#[test]
+	fn patches() {
+		let mut layout = Layout::new();
+		layout.add_section(Section::from_raw(0x10, vec![0; 4], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x14, vec![0; 4], SectionFlags::Read)).unwrap();
+		layout.add_section(Section::from_raw(0x1A, vec![7; 2], SectionFlags::Read)).unwrap();
+		layout.write_u32(0x12, 0x11223344).unwrap();
+		assert_eq!(layout.read_u32(0x12).unwrap(), 0x11223344);
+		assert!(layout.write_u32(0x17, 0).is_err());
+		layout.write_u8(0x13, 0).unwrap();
+		assert_eq!(layout.patches().len(), 2);
+		assert_eq!(layout.patched_ranges(), vec![
+			Patch {address: 0x12, original: vec![0], patched: vec![0x11]},
+			Patch {address: 0x14, original: vec![0, 0], patched: vec![0x33, 0x44]},
+		]);
 
-		Some(section)
+		assert!(layout.undo());
+		assert_eq!(layout.read_u8(0x13).unwrap(), 0x22);
+		assert!(layout.undo());
+		assert!(!layout.undo());
+		assert_eq!(layout.read_u32(0x12).unwrap(), 0);
+		assert!(layout.patched_ranges().is_empty());
+		assert!(layout.redo());
+		assert_eq!(layout.read_u32(0x12).unwrap(), 0x11223344);
+
+		// A new patch clears the redo history
+		layout.write_u8(0x10, 1).unwrap();
+		assert!(!layout.redo());
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,637 @@
+use crate::architecture::{Architecture, Disassembly, RegisterId};
+use crate::cfg::{Cfg, EdgeKind};
+use crate::function::{BasicBlock, Function};
+use crate::il::{self, InstructionId, InstructionTree};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+
+
+/// Storage renamed by SSA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Variable {
+	Register(RegisterId),
+	Flag(RegisterId),
+	Memory,	// All of memory as one variable
+}
+
+
+
+/// Version of a variable. Version 0 is the value on entry to the function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SsaVariable {
+	pub variable: Variable,
+	pub version: usize,
+}
+
+
+
+/// Place where a version is defined or used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+	Entry,						// Value on entry to the function
+	Phi(usize, usize),			// Block start and index of the phi
+	Statement(usize, usize),	// Block start and index of the statement
+}
+
+
+
+/// Merge of the versions reaching a join point
+#[derive(Debug)]
+pub struct Phi {
+	pub definition: SsaVariable,
+	/// Version coming from each predecessor, by block start. None is the caller of the function
+	pub sources: Vec<(Option<usize>, SsaVariable)>,
+}
+
+
+
+/// Lifted IL statement with the versions it reads and writes
+#[derive(Debug)]
+pub struct Statement {
+	/// Address of the architecture instruction
+	pub address: usize,
+	pub tree: InstructionTree,
+	/// Version read by each node. `Pop` reads both the stack pointer and memory
+	pub uses: Vec<(InstructionId, SsaVariable)>,
+	/// Versions written, in order
+	pub definitions: Vec<SsaVariable>,
+}
+
+
+
+#[derive(Debug)]
+pub struct SsaBlock {
+	pub start: usize,
+	/// Address after the last instruction
+	pub end: usize,
+	pub phis: Vec<Phi>,
+	pub statements: Vec<Statement>,
+}
+
+
+
+/// Static single assignment form of a function, with def-use chains.
+/// Only the blocks reachable from the entry are included.
+#[derive(Debug)]
+pub struct SsaFunction {
+	entry: usize,
+	blocks: BTreeMap<usize, SsaBlock>,
+	definitions: BTreeMap<SsaVariable, Location>,
+	uses: BTreeMap<SsaVariable, Vec<Location>>,
+}
+
+
+
+/// Renames variables while walking the dominator tree
+struct Renamer {
+	stack_pointer: Option<RegisterId>,
+	clobbered: Vec<Variable>,	// Changed by calls and traps
+	registers: Vec<Variable>,	// Every register and flag of the function, changed by unimplemented statements
+	counters: BTreeMap<Variable, usize>,
+	current: BTreeMap<Variable, Vec<usize>>,
+}
+
+
+
+/// Returns true if the variable can be live at the start of a block. Temporaries only live within an instruction
+fn lives_across_blocks(variable: Variable) -> bool {
+	!matches!(variable, Variable::Register(id) if id.id >= il::TEMPORARY_ID)
+}
+
+
+
+/// Returns the registers and flags read by the tree
+fn variables_read(tree: &InstructionTree) -> BTreeSet<Variable> {
+	tree.nodes().iter().filter_map(|node| match node {
+		il::Instruction::GetRegister(register) => Some(Variable::Register(register.id)),
+		il::Instruction::GetFlag(flag) => Some(Variable::Flag(flag.0)),
+		_ => None,
+	}).collect()
+}
+
+
+
+/// Moves the operands of a control transfer into temporaries. Returns the statements
+/// saving the operands and the transfer using the temporaries.
+fn save_operands(tree: &InstructionTree) -> (Vec<InstructionTree>, InstructionTree) {
+	let root = match tree.root().and_then(|root| tree.get(root)) {
+		Some(root) => *root,
+		None => return (Vec::new(), InstructionTree::new()),
+	};
+
+	let mut saved = Vec::new();
+	let mut control = InstructionTree::new();
+	// Temporaries 0 and 1 are used by the lifted instructions themselves
+	let temporaries: Vec<il::Register> = root.operands().iter().enumerate().map(|(i, operand)| {
+		let mut value = tree.subtree(*operand);
+		let size = value.root().and_then(|root| value.get(root)).and_then(|node| node.size()).unwrap_or(il::Size::Int32);
+		let temporary = il::Register::temporary(2 + i, size);
+		if let Some(root) = value.root() {
+			value.add_root(il::Instruction::SetRegister(temporary, root));
+		}
+		saved.push(value);
+		temporary
+	}).collect();
+
+	let mut operands = temporaries.iter().map(|temporary| control.add_node(il::Instruction::GetRegister(*temporary))).collect::<Vec<_>>().into_iter();
+	control.add_root(root.map_operands(|_| operands.next().unwrap()));
+	(saved, control)
+}
+
+
+
+/// Lifts the instructions of the block. A delayed branch transfers control after its
+/// delay slot, so values read by the branch that the slot changes are saved first.
+fn lift_block(arch: &dyn Architecture, disassembly: &Disassembly, block: &BasicBlock) -> Vec<(usize, InstructionTree)> {
+	let mut statements = Vec::new();
+	let mut address = block.start;
+
+	while address < block.end {
+		let (instruction, size) = match disassembly.get(address) {
+			Some(result) => result,
+			None => break,
+		};
+		let current = address;
+		let mut trees = arch.lift(instruction, current);
+		address += size;
+
+		let delay_slots = instruction.info().delay_slots;
+		if delay_slots == 0 {
+			statements.extend(trees.into_iter().map(|tree| (current, tree)));
+			continue;
+		}
+
+		// Only the last statement transfers control; the others take effect immediately
+		let control = trees.pop();
+		statements.extend(trees.into_iter().map(|tree| (current, tree)));
+
+		let mut slot = Vec::new();
+		for _ in 0..delay_slots {
+			match disassembly.get(address) {
+				Some((instruction, size)) if address < block.end => {
+					slot.extend(arch.lift(instruction, address).into_iter().map(|tree| (address, tree)));
+					address += size;
+				}
+				_ => break,
+			}
+		}
+
+		let mut control = match control {
+			Some(control) => control,
+			None => {
+				statements.extend(slot);
+				continue;
+			}
+		};
+		let read = variables_read(&control);
+		let changed = slot.iter().any(|(_, tree)| match tree.root().and_then(|root| tree.get(root)) {
+			Some(il::Instruction::SetRegister(register, _)) => read.contains(&Variable::Register(register.id)),
+			Some(il::Instruction::SetFlag(flag, _)) => read.contains(&Variable::Flag(flag.0)),
+			Some(il::Instruction::Push(..)) => true,
+			_ => tree.nodes().iter().any(|node| matches!(node, il::Instruction::Pop(_))),
+		});
+		if changed {
+			let (saved, transfer) = save_operands(&control);
+			statements.extend(saved.into_iter().map(|tree| (current, tree)));
+			control = transfer;
+		}
+		statements.extend(slot);
+		statements.push((current, control));
+	}
+	statements
+}
+
+
+
+impl Renamer {
+	/// Returns the variables written by the statement
+	fn defined(&self, tree: &InstructionTree) -> Vec<Variable> {
+		let mut defined = Vec::new();
+		let stack_pointer = self.stack_pointer.map(Variable::Register);
+		if tree.nodes().iter().any(|node| matches!(node, il::Instruction::Pop(_))) {
+			defined.extend(stack_pointer);
+		}
+
+		match tree.root().and_then(|root| tree.get(root)) {
+			Some(il::Instruction::SetRegister(register, _)) => defined.push(Variable::Register(register.id)),
+			Some(il::Instruction::SetFlag(flag, _)) => defined.push(Variable::Flag(flag.0)),
+			Some(il::Instruction::Store(..)) => defined.push(Variable::Memory),
+			Some(il::Instruction::Push(..)) => {
+				defined.extend(stack_pointer);
+				defined.push(Variable::Memory);
+			}
+			Some(il::Instruction::Call(_)) | Some(il::Instruction::Trap(_)) => {
+				defined.push(Variable::Memory);
+				defined.extend(self.clobbered.iter().cloned());
+			}
+			// The effect is not known, so any register or memory may change
+			Some(il::Instruction::Unimplemented) => {
+				defined.extend(self.registers.iter().cloned());
+				defined.push(Variable::Memory);
+			}
+			_ => {}
+		}
+		defined
+	}
+
+	fn current(&self, variable: Variable) -> SsaVariable {
+		let version = self.current.get(&variable).and_then(|versions| versions.last()).cloned().unwrap_or(0);
+		SsaVariable {variable, version}
+	}
+
+	/// Creates the next version of the variable
+	fn define(&mut self, variable: Variable) -> SsaVariable {
+		let counter = self.counters.entry(variable).or_insert(0);
+		*counter += 1;
+		self.current.entry(variable).or_default().push(*counter);
+		SsaVariable {variable, version: *counter}
+	}
+
+	/// Records the versions read and written by the statement. Returns the variables defined.
+	fn rename(&mut self, statement: &mut Statement) -> Vec<Variable> {
+		let stack_pointer = self.stack_pointer.map(Variable::Register);
+		let mut uses = Vec::new();
+		for (id, node) in statement.tree.iter() {
+			match node {
+				il::Instruction::GetRegister(register) => uses.push((id, self.current(Variable::Register(register.id)))),
+				il::Instruction::GetFlag(flag) => uses.push((id, self.current(Variable::Flag(flag.0)))),
+				il::Instruction::Load(..) => uses.push((id, self.current(Variable::Memory))),
+				il::Instruction::Pop(_) => {
+					if let Some(stack_pointer) = stack_pointer {
+						uses.push((id, self.current(stack_pointer)));
+					}
+					uses.push((id, self.current(Variable::Memory)));
+				}
+				_ => {}
+			}
+		}
+		// Pushing reads the stack pointer it adjusts
+		if let (Some(root), Some(stack_pointer)) = (statement.tree.root(), stack_pointer) {
+			if matches!(statement.tree.get(root), Some(il::Instruction::Push(..))) {
+				uses.push((root, self.current(stack_pointer)));
+			}
+		}
+
+		let defined = self.defined(&statement.tree);
+		statement.uses = uses;
+		statement.definitions = defined.iter().map(|variable| self.define(*variable)).collect();
+		defined
+	}
+}
+
+
+
+impl SsaFunction {
+	/// Lifts the function and converts it to SSA form. Calls change memory and the registers
+	/// that are not callee-saved; their arguments and results are not modelled.
+	pub fn new(arch: &dyn Architecture, function: &Function, disassembly: &Disassembly) -> SsaFunction {
+		let cfg = Cfg::new(function, disassembly);
+		let dominators = cfg.dominators();
+		let frontiers = cfg.dominance_frontiers(&dominators);
+
+		let mut blocks: BTreeMap<usize, SsaBlock> = cfg.blocks().iter()
+			.filter(|block| dominators.dominates(cfg.entry(), block.start))
+			.map(|block| (block.start, SsaBlock {
+				start: block.start,
+				end: block.end,
+				phis: Vec::new(),
+				statements: lift_block(arch, disassembly, block).into_iter().map(|(address, tree)| Statement {
+					address,
+					tree,
+					uses: Vec::new(),
+					definitions: Vec::new(),
+				}).collect(),
+			}))
+			.collect();
+
+		// Every register and flag of the function except temporaries. Lifted trees must be valid
+		let mut registers: BTreeSet<Variable> = arch.stack_pointer().map(Variable::Register).into_iter().collect();
+		for statement in blocks.values().flat_map(|block| &block.statements) {
+			debug_assert!(statement.tree.validate().is_ok(), "invalid IL at {:X}: {:?}", statement.address, statement.tree.validate());
+			registers.extend(variables_read(&statement.tree));
+			match statement.tree.root().and_then(|root| statement.tree.get(root)) {
+				Some(il::Instruction::SetRegister(register, _)) => registers.insert(Variable::Register(register.id)),
+				Some(il::Instruction::SetFlag(flag, _)) => registers.insert(Variable::Flag(flag.0)),
+				_ => false,
+			};
+		}
+		registers.retain(|variable| lives_across_blocks(*variable));
+		let callee_saved = arch.callee_saved();
+		let mut renamer = Renamer {
+			stack_pointer: arch.stack_pointer(),
+			clobbered: registers.iter().filter(|variable| !matches!(variable, Variable::Register(id) if callee_saved.contains(id))).cloned().collect(),
+			registers: registers.iter().cloned().collect(),
+			counters: BTreeMap::new(),
+			current: BTreeMap::new(),
+		};
+
+		// Place phis at the dominance frontiers of the definitions
+		let mut sites: BTreeMap<Variable, BTreeSet<usize>> = BTreeMap::new();
+		for block in blocks.values() {
+			for statement in &block.statements {
+				for variable in renamer.defined(&statement.tree) {
+					sites.entry(variable).or_default().insert(block.start);
+				}
+			}
+		}
+		for (variable, definitions) in sites {
+			if !lives_across_blocks(variable) {
+				continue;
+			}
+			let mut placed = BTreeSet::new();
+			let mut worklist: Vec<usize> = definitions.iter().cloned().collect();
+			while let Some(block) = worklist.pop() {
+				for &join in frontiers.get(&block).into_iter().flatten() {
+					if !placed.insert(join) {
+						continue;
+					}
+					if let Some(target) = blocks.get_mut(&join) {
+						// Loops back to the entry merge with the value on entry
+						let sources = if join == cfg.entry() { vec![(None, SsaVariable {variable, version: 0})] } else { Vec::new() };
+						target.phis.push(Phi {definition: SsaVariable {variable, version: 0}, sources});
+					}
+					if !definitions.contains(&join) {
+						worklist.push(join);
+					}
+				}
+			}
+		}
+
+		// Rename in dominator tree preorder, undoing the definitions of a block after its subtree
+		enum Visit {
+			Enter(usize),
+			Exit(Vec<Variable>),
+		}
+		let mut stack = vec![Visit::Enter(cfg.entry())];
+		while let Some(visit) = stack.pop() {
+			let start = match visit {
+				Visit::Enter(start) => start,
+				Visit::Exit(defined) => {
+					for variable in defined {
+						renamer.current.get_mut(&variable).and_then(|versions| versions.pop());
+					}
+					continue;
+				}
+			};
+			let block = match blocks.get_mut(&start) {
+				Some(block) => block,
+				None => continue,
+			};
+
+			let mut defined = Vec::new();
+			for phi in &mut block.phis {
+				phi.definition = renamer.define(phi.definition.variable);
+				defined.push(phi.definition.variable);
+			}
+			for statement in &mut block.statements {
+				defined.extend(renamer.rename(statement));
+			}
+
+			let successors: BTreeSet<usize> = cfg.successors(start)
+				.filter(|edge| edge.kind != EdgeKind::Call)
+				.filter_map(|edge| edge.to)
+				.collect();
+			for successor in successors {
+				if let Some(successor) = blocks.get_mut(&successor) {
+					for phi in &mut successor.phis {
+						phi.sources.push((Some(start), renamer.current(phi.definition.variable)));
+					}
+				}
+			}
+
+			stack.push(Visit::Exit(defined));
+			let children: Vec<usize> = dominators.children(start).collect();
+			stack.extend(children.into_iter().rev().map(Visit::Enter));
+		}
+
+		let mut ssa = SsaFunction {
+			entry: cfg.entry(),
+			blocks,
+			definitions: BTreeMap::new(),
+			uses: BTreeMap::new(),
+		};
+		ssa.build_chains();
+		ssa
+	}
+
+	/// Indexes where each version is defined and used
+	fn build_chains(&mut self) {
+		for block in self.blocks.values() {
+			for (i, phi) in block.phis.iter().enumerate() {
+				let location = Location::Phi(block.start, i);
+				self.definitions.insert(phi.definition, location);
+				for (_, source) in &phi.sources {
+					self.uses.entry(*source).or_default().push(location);
+				}
+			}
+			for (i, statement) in block.statements.iter().enumerate() {
+				let location = Location::Statement(block.start, i);
+				for definition in &statement.definitions {
+					self.definitions.insert(*definition, location);
+				}
+				for (_, used) in &statement.uses {
+					let uses = self.uses.entry(*used).or_default();
+					if !uses.contains(&location) {
+						uses.push(location);
+					}
+				}
+			}
+		}
+		for used in self.uses.keys() {
+			if used.version == 0 {
+				self.definitions.insert(*used, Location::Entry);
+			}
+		}
+	}
+
+	/// Returns the start of the entry block
+	pub fn entry(&self) -> usize {
+		self.entry
+	}
+
+	/// Returns the reachable blocks ordered by address
+	pub fn blocks(&self) -> impl Iterator<Item = &SsaBlock> {
+		self.blocks.values()
+	}
+
+	/// Returns the block starting at the address
+	pub fn block(&self, start: usize) -> Option<&SsaBlock> {
+		self.blocks.get(&start)
+	}
+
+	/// Returns the statement at the location
+	pub fn statement(&self, location: Location) -> Option<&Statement> {
+		match location {
+			Location::Statement(block, index) => self.blocks.get(&block)?.statements.get(index),
+			_ => None,
+		}
+	}
+
+	/// Returns the phi at the location
+	pub fn phi(&self, location: Location) -> Option<&Phi> {
+		match location {
+			Location::Phi(block, index) => self.blocks.get(&block)?.phis.get(index),
+			_ => None,
+		}
+	}
+
+	/// Returns where the version is defined. Versions that are never defined or used are unknown
+	pub fn definition(&self, variable: SsaVariable) -> Option<Location> {
+		self.definitions.get(&variable).cloned()
+	}
+
+	/// Returns the phis and statements reading the version
+	pub fn uses(&self, variable: SsaVariable) -> &[Location] {
+		self.uses.get(&variable).map_or(&[], |uses| uses)
+	}
+
+	/// Returns every defined version with its location
+	pub fn definitions(&self) -> impl Iterator<Item = (&SsaVariable, &Location)> {
+		self.definitions.iter()
+	}
+}
+
+
+
+impl fmt::Display for Variable {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Variable::Register(id) | Variable::Flag(id) => write!(f, "{}", id),
+			Variable::Memory => write!(f, "mem"),
+		}
+	}
+}
+
+
+
+impl fmt::Display for SsaVariable {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}#{}", self.variable, self.version)
+	}
+}
+
+
+
+impl fmt::Display for Phi {
+	/// Writes the phi, e.g. `R1#3 = phi(R1#1, R1#2)`
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let sources: Vec<String> = self.sources.iter().map(|(_, source)| source.to_string()).collect();
+		write!(f, "{} = phi({})", self.definition, sources.join(", "))
+	}
+}
+
+
+
+impl fmt::Display for Statement {
+	/// Writes the statement with the version of each register and flag, e.g. `R1#2 = R1#1 + 4`
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let root = self.tree.root();
+		let versions = |id: InstructionId| {
+			if Some(id) == root {
+				let variable = match self.tree.get(id)? {
+					il::Instruction::SetRegister(register, _) => Variable::Register(register.id),
+					il::Instruction::SetFlag(flag, _) => Variable::Flag(flag.0),
+					_ => return None,
+				};
+				return self.definitions.iter().rev().find(|definition| definition.variable == variable).map(|definition| definition.version);
+			}
+			self.uses.iter()
+				.find(|(node, used)| *node == id && used.variable != Variable::Memory)
+				.map(|(_, used)| used.version)
+		};
+		write!(f, "{}", self.tree.display_versions(&versions))
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::workspace::code_workspace;
+
+	/// Converts the function at 0x1000 of big-endian SH-2 words
+	fn ssa(words: &[u16]) -> SsaFunction {
+		let mut ws = code_workspace(words);
+		ws.analyze();
+		ws.ssa(0x1000).unwrap()
+	}
+
+	/// Returns the statements of the instruction at the address
+	fn statements(ssa: &SsaFunction, address: usize) -> Vec<&Statement> {
+		ssa.blocks().flat_map(|block| &block.statements).filter(|statement| statement.address == address).collect()
+	}
+
+	#[test]
+	fn unimplemented_changes_memory() {
+		// mov.l @R1,R0; sleep; mov.l @R1,R2; rts; nop
+		let ssa = ssa(&[0x6012, 0x001B, 0x6212, 0x000B, 0x0009]);
+		let memory = |address: usize| statements(&ssa, address)[0].uses.iter()
+			.find(|(_, used)| used.variable == Variable::Memory)
+			.map(|(_, used)| used.version);
+		assert_eq!(memory(0x1000), Some(0));
+		assert!(statements(&ssa, 0x1002)[0].definitions.contains(&SsaVariable {variable: Variable::Memory, version: 1}));
+		assert_eq!(memory(0x1004), Some(1));
+	}
+
+	/// Renders the phis and statements of each block
+	fn listing(ssa: &SsaFunction) -> Vec<String> {
+		ssa.blocks().flat_map(|block| block.phis.iter().map(|phi| phi.to_string()).chain(block.statements.iter().map(|statement| statement.to_string()))).collect()
+	}
+
+	#[test]
+	fn loop_to_entry() {
+		// 1000: dt R4; bf 1000; rts; nop
+		let ssa = ssa(&[0x4410, 0x8BFD, 0x000B, 0x0009]);
+		assert_eq!(listing(&ssa), [
+			"R4#1 = phi(R4#0, R4#2)",
+			"T#1 = phi(T#0, T#2)",
+			"R4#2 = R4#1 - 1",
+			"T#2 = R4#2 == 0",
+			"if (~T#2) jump(0x1000)",
+			"return(PR#0)",
+		]);
+
+		let phi = ssa.phi(Location::Phi(0x1000, 0)).unwrap();
+		let r4 = |version: usize| SsaVariable {variable: phi.definition.variable, version};
+		assert_eq!(phi.sources, [(None, r4(0)), (Some(0x1000), r4(2))]);
+		assert_eq!(ssa.definition(r4(0)), Some(Location::Entry));
+		assert_eq!(ssa.definition(r4(2)), Some(Location::Statement(0x1000, 0)));
+		assert_eq!(ssa.uses(r4(2)), [Location::Phi(0x1000, 0), Location::Statement(0x1000, 1)]);
+		assert_eq!(ssa.uses(r4(1)), [Location::Statement(0x1000, 0)]);
+	}
+
+	#[test]
+	fn join() {
+		// 1000: bt 1008; mov #1,R0; bra 100A; nop
+		// 1008: mov #2,R0
+		// 100A: rts; nop
+		let ssa = ssa(&[0x8902, 0xE001, 0xA001, 0x0009, 0xE002, 0x000B, 0x0009]);
+		assert_eq!(listing(&ssa), [
+			"if (T#0) jump(0x1008)",
+			"R0#1 = 1",
+			"jump(0x100A)",
+			"R0#2 = 2",
+			"R0#3 = phi(R0#1, R0#2)",
+			"return(PR#0)",
+		]);
+		assert_eq!(ssa.block(0x100A).unwrap().phis[0].sources.iter().map(|(block, _)| *block).collect::<Vec<_>>(), [Some(0x1002), Some(0x1008)]);
+	}
+
+	#[test]
+	fn calls() {
+		// 1000: mov #1,R8; mov #1,R0; bsr 100E; nop; add R8,R0; rts; nop
+		// Calls change R0 and PR but preserve R8
+		let ssa = ssa(&[0xE801, 0xE001, 0xB003, 0x0009, 0x308C, 0x000B, 0x0009, 0x000B, 0x0009]);
+		assert_eq!(listing(&ssa), [
+			"R8#1 = 1",
+			"R0#1 = 1",
+			"PR#1 = 0x1008",
+			"call(0x100E)",
+			"R0#3 = R0#2 + R8#1",
+			"return(PR#2)",
+		]);
+	}
+}
@@ -0,0 +1,207 @@
+use crate::architecture::{Disassembly, Operand};
+use crate::error::{Error, Result};
+use crate::function::Functions;
+use crate::xref::{Xref, XrefKind, Xrefs};
+
+use std::collections::BTreeMap;
+
+
+
+/// Origin of a symbol. Names from a higher source replace names from lower ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolSource {
+	Auto,		// Generated from the address (e.g. `sub_1000`)
+	Analysis,	// Derived by an analysis (e.g. exception vector names)
+	Imported,	// Read from the loaded file
+	User,
+}
+
+
+
+/// Name of an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+	pub address: usize,
+	pub name: String,
+	pub source: SymbolSource,
+}
+
+
+
+/// Symbol table with lookups by address and by name
+#[derive(Default)]
+pub struct Symbols {
+	symbols: BTreeMap<usize, Symbol>,
+	names: BTreeMap<String, usize>,
+}
+
+
+
+/// Returns the size of the data accessed by the reference, or 0 if it is not known
+fn access_size(disassembly: &Disassembly, xref: &Xref) -> usize {
+	let instruction = match disassembly.instruction_at(xref.from) {
+		Some(instruction) => instruction,
+		None => return 0,
+	};
+	instruction.operands().iter().find_map(|operand| match operand {
+		Operand::Memory(memory) => Some(memory.size),
+		_ => None,
+	}).unwrap_or(0)
+}
+
+
+
+impl Symbols {
+	pub fn new() -> Symbols {
+		Symbols::default()
+	}
+
+	/// Names the address. Returns false if it already has a name from a higher source.
+	/// Fails if the name belongs to another address.
+	pub fn insert(&mut self, address: usize, name: &str, source: SymbolSource) -> Result<bool> {
+		if name.is_empty() {
+			return Err(Error::InvalidArgument("empty symbol name".to_string()));
+		}
+		if let Some(&other) = self.names.get(name) {
+			if other != address {
+				return Err(Error::DuplicateSymbol(name.to_string()));
+			}
+		}
+
+		if let Some(existing) = self.symbols.get(&address) {
+			if existing.source > source {
+				return Ok(false);
+			}
+			self.names.remove(&existing.name);
+		}
+
+		self.names.insert(name.to_string(), address);
+		self.symbols.insert(address, Symbol {
+			address,
+			name: name.to_string(),
+			source,
+		});
+		Ok(true)
+	}
+
+	/// Removes the name of the address
+	pub fn remove(&mut self, address: usize) -> Option<Symbol> {
+		let symbol = self.symbols.remove(&address)?;
+		self.names.remove(&symbol.name);
+		Some(symbol)
+	}
+
+	pub fn get(&self, address: usize) -> Option<&Symbol> {
+		self.symbols.get(&address)
+	}
+
+	/// Returns the name of the address
+	pub fn name(&self, address: usize) -> Option<&str> {
+		self.symbols.get(&address).map(|symbol| symbol.name.as_str())
+	}
+
+	/// Returns the address with the name
+	pub fn lookup(&self, name: &str) -> Option<usize> {
+		self.names.get(name).cloned()
+	}
+
+	/// Returns the symbols ordered by address
+	pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+		self.symbols.values()
+	}
+
+	pub fn len(&self) -> usize {
+		self.symbols.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.symbols.is_empty()
+	}
+
+	/// Regenerates the automatic names: `sub_` for functions, `loc_` for other branch targets,
+	/// and `byte_`, `word_`, `dword_` or `unk_` for data depending on the access size
+	pub fn auto_name(&mut self, disassembly: &Disassembly, functions: &Functions, xrefs: &Xrefs) {
+		let auto: Vec<usize> = self.symbols.values()
+			.filter(|symbol| symbol.source == SymbolSource::Auto)
+			.map(|symbol| symbol.address)
+			.collect();
+		for address in auto {
+			self.remove(address);
+		}
+
+		// Automatic names are unique per address, so they only conflict with names chosen by the user
+		for function in functions.iter() {
+			if self.get(function.start()).is_none() {
+				self.insert(function.start(), &format!("sub_{:X}", function.start()), SymbolSource::Auto).ok();
+			}
+		}
+
+		for xref in xrefs.iter() {
+			if self.get(xref.to).is_some() {
+				continue;
+			}
+			let name = match xref.kind {
+				XrefKind::Jump => format!("loc_{:X}", xref.to),
+				XrefKind::Read | XrefKind::Write => {
+					let prefix = match access_size(disassembly, xref) {
+						1 => "byte",
+						2 => "word",
+						4 => "dword",
+						_ => "unk",
+					};
+					format!("{}_{:X}", prefix, xref.to)
+				}
+				// Calls and pointers lead to functions, which are already named
+				XrefKind::Call | XrefKind::Pointer => continue,
+			};
+			self.insert(xref.to, &name, SymbolSource::Auto).ok();
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::workspace::code_workspace;
+
+	#[test]
+	fn sources() {
+		let mut symbols = Symbols::new();
+		assert!(symbols.insert(0x1000, "sub_1000", SymbolSource::Auto).unwrap());
+		assert!(symbols.insert(0x1000, "reset", SymbolSource::Imported).unwrap());
+		assert!(!symbols.insert(0x1000, "sub_1000", SymbolSource::Auto).unwrap());
+		assert_eq!(symbols.name(0x1000), Some("reset"));
+		assert_eq!(symbols.lookup("sub_1000"), None);
+		assert_eq!(symbols.lookup("reset"), Some(0x1000));
+
+		assert!(matches!(symbols.insert(0x2000, "reset", SymbolSource::User), Err(Error::DuplicateSymbol(_))));
+		assert!(matches!(symbols.insert(0x2000, "", SymbolSource::User), Err(Error::InvalidArgument(_))));
+		assert_eq!(symbols.len(), 1);
+
+		assert_eq!(symbols.remove(0x1000).map(|symbol| symbol.source), Some(SymbolSource::Imported));
+		assert!(symbols.is_empty() && symbols.lookup("reset").is_none());
+	}
+
+	#[test]
+	fn auto_names() {
+		// 1000: mov.l @(100C),R1; bt 1008; bsr 1010; nop
+		// 1008: rts; nop; .long FFFF8000
+		// 1010: rts; nop
+		let mut ws = code_workspace(&[0xD102, 0x8901, 0xB004, 0x0009, 0x000B, 0x0009, 0xFFFF, 0x8000, 0x000B, 0x0009]);
+		ws.analyze();
+
+		let names: Vec<(usize, &str)> = ws.symbols.iter().map(|symbol| (symbol.address, symbol.name.as_str())).collect();
+		assert_eq!(names, vec![(0x1000, "sub_1000"), (0x1008, "loc_1008"), (0x100C, "dword_100C"), (0x1010, "sub_1010")]);
+
+		// User names survive the next analysis and replace addresses in the listing
+		ws.rename(0x1010, "helper").unwrap();
+		assert!(ws.rename(0x1008, "helper").is_err());
+		ws.analyze();
+		assert_eq!(ws.symbols.get(0x1010).map(|symbol| symbol.source), Some(SymbolSource::User));
+		assert_eq!(ws.functions.get(0x1010).unwrap().name(), "helper");
+		let instruction = ws.disassembly.instruction_at(0x1004).unwrap();
+		assert_eq!(instruction.display(&ws.symbols).to_string(), "bsr helper");
+	}
+}
@@ -1,16 +1,157 @@
 use crate::memory::Layout;
-use crate::architecture::Architecture;
+use crate::architecture::{Architecture, Disassembly, Vector};
+use crate::cfg::Cfg;
+use crate::error::{Error, Result};
+use crate::function::Functions;
+use crate::memory::SectionFlags;
+use crate::ssa::SsaFunction;
+use crate::symbol::{SymbolSource, Symbols};
+use crate::xref::{Xref, XrefKind, Xrefs};
+
 
 pub struct Workspace {
 	pub memory: Layout,
-	pub arch: Box<Architecture>,
+	pub arch: Box<dyn Architecture>,
+	/// Names of addresses
+	pub symbols: Symbols,
+	/// Addresses execution starts from, as given by the loaded file
+	pub entry_points: Vec<usize>,
+	/// Result of the recursive traversal, see `analyze`
+	pub disassembly: Disassembly,
+	/// Functions found by `analyze`
+	pub functions: Functions,
+	/// References between code and data found by `analyze`
+	pub xrefs: Xrefs,
 }
 
 impl Workspace {
-	pub fn new(arch: Box<Architecture>) -> Workspace {
+	pub fn new(arch: Box<dyn Architecture>) -> Workspace {
 		Workspace {
 			memory: Layout::new(),
 			arch,
+			symbols: Symbols::new(),
+			entry_points: Vec::new(),
+			disassembly: Disassembly::new(),
+			functions: Functions::new(),
+			xrefs: Xrefs::new(),
+		}
+	}
+
+	/// Reads the exception vector table at `base` (the VBR), names the handlers
+	/// and adds them as entry points. Returns the entries that were found.
+	pub fn add_vector_table(&mut self, base: usize) -> Vec<Vector> {
+		let vectors = self.arch.vector_table(&self.memory, base);
+		for vector in vectors.iter().filter(|vector| vector.handler) {
+			// Handlers shared by several vectors keep the first name
+			if self.symbols.get(vector.value).is_none() {
+				self.symbols.insert(vector.value, &vector.name, SymbolSource::Analysis).ok();
+			}
+			if !self.entry_points.contains(&vector.value) {
+				self.entry_points.push(vector.value);
+			}
+		}
+		vectors
+	}
+
+	/// Disassembles everything reachable from the entry points and function prologues,
+	/// then detects the functions and references and names them. Only entry points added since the previous call are traversed.
+	pub fn analyze(&mut self) {
+		for address in &self.entry_points {
+			self.disassembly.add_entry_point(*address);
+		}
+		// Prologues are searched after the traversal has found the literals
+		self.disassembly.analyze(self.arch.as_ref(), &self.memory);
+		for address in self.arch.find_prologues(&self.memory, &self.disassembly) {
+			self.disassembly.add_entry_point(address);
 		}
+		self.disassembly.analyze(self.arch.as_ref(), &self.memory);
+		self.functions = Functions::from_disassembly(&self.disassembly, &self.symbols);
+		self.xrefs = Xrefs::build(self.arch.as_ref(), &self.memory, &self.disassembly, &self.functions);
+		self.symbols.auto_name(&self.disassembly, &self.functions, &self.xrefs);
+	}
+
+	/// Gives the address a user-defined name, replacing any other name
+	pub fn rename(&mut self, address: usize, name: &str) -> Result<()> {
+		self.symbols.insert(address, name, SymbolSource::User)?;
+		self.functions.rename(address, name);
+		Ok(())
+	}
+
+	/// Builds the control flow graph of the function starting at the address
+	pub fn cfg(&self, function: usize) -> Option<Cfg> {
+		Some(Cfg::new(self.functions.get(function)?, &self.disassembly))
+	}
+
+	/// Lifts the function starting at the address into SSA form
+	pub fn ssa(&self, function: usize) -> Option<SsaFunction> {
+		Some(SsaFunction::new(self.arch.as_ref(), self.functions.get(function)?, &self.disassembly))
+	}
+
+	/// Returns the writes found by `analyze` whose destination is in a section without the Write flag
+	pub fn read_only_writes(&self) -> Vec<Xref> {
+		self.xrefs.iter()
+			.filter(|xref| xref.kind == XrefKind::Write)
+			.filter(|xref| matches!(self.memory.check_access(xref.to, 1, SectionFlags::Write), Err(Error::ReadOnly)))
+			.copied()
+			.collect()
+	}
+}
+
+/// Creates an SH-2E workspace with the big-endian words at 0x1000 as code and an entry point at 0x1000
+#[cfg(test)]
+pub(crate) fn code_workspace(words: &[u16]) -> Workspace {
+	let mut ws = Workspace::new(Box::new(crate::architecture::sh2e::SH2E::new()));
+	ws.memory = crate::memory::code_layout(words);
+	ws.entry_points.push(0x1000);
+	ws
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::Section;
+
+	#[test]
+	fn analyze() {
+		// bra 1006; nop; data; rts; nop
+		let mut ws = code_workspace(&[0xA001, 0x0009, 0xFFFF, 0x000B, 0x0009]);
+		ws.analyze();
+		assert_eq!(ws.disassembly.len(), 4);
+		assert!(!ws.disassembly.is_code(0x1004));
+
+		// Only the new entry point is traversed
+		ws.entry_points.push(0x1004);
+		ws.analyze();
+		assert_eq!(ws.disassembly.invalid().collect::<Vec<_>>(), vec![0x1004]);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn vector_table_entry_points() {
+		// Power-on and manual reset share a handler
+		let mut ws = code_workspace(&[0x0000, 0x1014, 0xFFFF, 0x8000, 0x0000, 0x1014, 0xFFFF, 0x8000, 0x0000, 0x1018, 0x000B, 0x0009, 0x000B, 0x0009]);
+		assert_eq!(ws.add_vector_table(0x1000).len(), 5);
+		assert_eq!(ws.entry_points, vec![0x1000, 0x1014, 0x1018]);
+		assert_eq!(ws.symbols.name(0x1014), Some("power_on_reset"));
+		assert_eq!(ws.symbols.name(0x1018), Some("general_illegal_instruction"));
+		ws.add_vector_table(0x1000);
+		assert_eq!(ws.entry_points.len(), 3);
+	}
+
+	#[test]
+	fn control_flow_graph() {
+		// bt 1006; rts; nop; rts; nop
+		let mut ws = code_workspace(&[0x8901, 0x000B, 0x0009, 0x000B, 0x0009]);
+		ws.analyze();
+		assert_eq!(ws.cfg(0x1000).unwrap().blocks().len(), 3);
+		assert!(ws.cfg(0x1002).is_none());
+	}
+
+	#[test]
+	fn writes_to_read_only_memory() {
+		// mov.l @(0x100C), R1 ; mov.l R0, @R1 ; mov.l @(0x1010), R2 ; mov.l R0, @R2 ; rts ; nop ; .long 0x1000, 0xFFFF8000
+		let mut ws = code_workspace(&[0xD102, 0x2102, 0xD202, 0x2202, 0x000B, 0x0009, 0x0000, 0x1000, 0xFFFF, 0x8000]);
+		ws.memory.add_section(Section::from_raw(0xFFFF8000, vec![0; 16], SectionFlags::ReadWrite | SectionFlags::Data)).unwrap();
+		ws.analyze();
+		assert_eq!(ws.read_only_writes(), vec![Xref {from: 0x1002, to: 0x1000, kind: XrefKind::Write}]);
+	}
+}
@@ -0,0 +1,208 @@
+use crate::architecture::{Architecture, BranchKind, Disassembly, Operand};
+use crate::dataflow::Dataflow;
+use crate::function::Functions;
+use crate::memory::{Layout, SectionFlags};
+use crate::ssa::SsaFunction;
+
+use std::collections::BTreeMap;
+
+
+
+/// Kind of reference between two addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XrefKind {
+	Call,		// Code calling a function
+	Jump,		// Code branching to code
+	Read,		// Code reading memory
+	Write,		// Code writing memory
+	Pointer,	// Data holding the address of a function (e.g. vector and pointer tables)
+}
+
+
+
+/// Reference from one address to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xref {
+	pub from: usize,
+	pub to: usize,
+	pub kind: XrefKind,
+}
+
+
+
+/// Cross-reference index with lookups by source and destination
+#[derive(Default)]
+pub struct Xrefs {
+	from: BTreeMap<usize, Vec<Xref>>,
+	to: BTreeMap<usize, Vec<Xref>>,
+}
+
+
+
+impl XrefKind {
+	/// Returns the access performed at the destination
+	pub fn access(self) -> SectionFlags {
+		match self {
+			XrefKind::Call | XrefKind::Jump | XrefKind::Pointer => SectionFlags::Execute,
+			XrefKind::Read => SectionFlags::Read,
+			XrefKind::Write => SectionFlags::Write,
+		}
+	}
+}
+
+
+
+impl Xrefs {
+	pub fn new() -> Xrefs {
+		Xrefs::default()
+	}
+
+	/// Collects the references made by the decoded code and the pointers to functions stored in data.
+	/// Memory accesses are resolved by dataflow over the IL of each function, which finds the
+	/// literal pool loads and the registers holding constant addresses (e.g. GBR).
+	pub fn build(arch: &dyn Architecture, layout: &Layout, disassembly: &Disassembly, functions: &Functions) -> Xrefs {
+		let mut xrefs = Xrefs::new();
+
+		for block in functions.iter().flat_map(|function| function.blocks()) {
+			let mut address = block.start;
+			while address < block.end {
+				let (instruction, size) = match disassembly.get(address) {
+					Some(result) => result,
+					None => break,
+				};
+
+				match disassembly.branch_target(address) {
+					Some(target) => {
+						let kind = match instruction.info().branch {
+							BranchKind::Call(_) => XrefKind::Call,
+							_ => XrefKind::Jump,
+						};
+						xrefs.add(Xref {from: address, to: target, kind});
+					}
+					None => {
+						// Address of data computed by the instruction (e.g. `mova`)
+						for operand in instruction.operands() {
+							if let Operand::Target(to) = operand {
+								xrefs.add(Xref {from: address, to: *to, kind: XrefKind::Read});
+							}
+						}
+					}
+				}
+				address += size;
+			}
+		}
+
+		for function in functions.iter() {
+			let dataflow = Dataflow::new(&SsaFunction::new(arch, function, disassembly), layout);
+			for access in dataflow.accesses() {
+				if access.access.contains(SectionFlags::Read) {
+					xrefs.add(Xref {from: access.from, to: access.to, kind: XrefKind::Read});
+				}
+				if access.access.contains(SectionFlags::Write) {
+					xrefs.add(Xref {from: access.from, to: access.to, kind: XrefKind::Write});
+				}
+			}
+		}
+
+		// Pointers are 32-bit aligned words outside of the code
+		for section in layout.sections().filter(|section| section.flags().contains(SectionFlags::Read)) {
+			let mut address = (section.address() + 3) & !3;
+			while address + 4 <= section.end() {
+				if !disassembly.is_code(address) {
+					if let Ok(value) = layout.read_u32(address) {
+						if functions.get(value as usize).is_some() {
+							xrefs.add(Xref {from: address, to: value as usize, kind: XrefKind::Pointer});
+						}
+					}
+				}
+				address += 4;
+			}
+		}
+
+		xrefs
+	}
+
+	/// Adds the reference unless it is already known
+	pub fn add(&mut self, xref: Xref) {
+		let from = self.from.entry(xref.from).or_default();
+		if from.contains(&xref) {
+			return;
+		}
+		from.push(xref);
+		self.to.entry(xref.to).or_default().push(xref);
+	}
+
+	/// Returns the references made by the address
+	pub fn from(&self, address: usize) -> &[Xref] {
+		self.from.get(&address).map_or(&[], |xrefs| xrefs)
+	}
+
+	/// Returns the references to the address
+	pub fn to(&self, address: usize) -> &[Xref] {
+		self.to.get(&address).map_or(&[], |xrefs| xrefs)
+	}
+
+	/// Returns every reference ordered by source
+	pub fn iter(&self) -> impl Iterator<Item = &Xref> {
+		self.from.values().flatten()
+	}
+
+	pub fn len(&self) -> usize {
+		self.from.values().map(|xrefs| xrefs.len()).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.from.is_empty()
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::architecture::sh2e::SH2E;
+	use crate::memory::code_layout;
+	use crate::symbol::Symbols;
+
+	/// Shorthand for a reference
+	fn xref(from: usize, to: usize, kind: XrefKind) -> Xref {
+		Xref {from, to, kind}
+	}
+
+	#[test]
+	fn build() {
+		// 1000: mov.l @(1014),R1; mov.l @R1,R0; ldc R1,GBR; mov.l R0,@(4,GBR); bsr 1010; nop; rts; nop
+		// 1010: rts; nop; .long FFFF8000; .long 1010
+		let layout = code_layout(&[0xD104, 0x6012, 0x411E, 0xC201, 0xB002, 0x0009, 0x000B, 0x0009, 0x000B, 0x0009, 0xFFFF, 0x8000, 0x0000, 0x1010]);
+
+		let arch = SH2E::new();
+		let mut disassembly = Disassembly::new();
+		disassembly.add_entry_point(0x1000);
+		disassembly.analyze(&arch, &layout);
+		let functions = Functions::from_disassembly(&disassembly, &Symbols::new());
+		let xrefs = Xrefs::build(&arch, &layout, &disassembly, &functions);
+
+		assert_eq!(xrefs.from(0x1000), &[xref(0x1000, 0x1014, XrefKind::Read)]);
+		assert_eq!(xrefs.from(0x1002), &[xref(0x1002, 0xFFFF8000, XrefKind::Read)]);
+		assert_eq!(xrefs.from(0x1006), &[xref(0x1006, 0xFFFF8004, XrefKind::Write)]);
+		assert_eq!(xrefs.to(0x1010), &[xref(0x1008, 0x1010, XrefKind::Call), xref(0x1018, 0x1010, XrefKind::Pointer)]);
+		assert_eq!(xrefs.len(), 5);
+		assert!(xrefs.from(0x1004).is_empty());
+	}
+
+	#[test]
+	fn index() {
+		let mut xrefs = Xrefs::new();
+		assert!(xrefs.is_empty());
+		xrefs.add(xref(0x1000, 0x2000, XrefKind::Read));
+		xrefs.add(xref(0x1000, 0x2000, XrefKind::Read));
+		xrefs.add(xref(0x1000, 0x2000, XrefKind::Write));
+		xrefs.add(xref(0x0FFE, 0x2000, XrefKind::Jump));
+		assert_eq!(xrefs.len(), 3);
+		assert_eq!(xrefs.iter().map(|xref| xref.from).collect::<Vec<_>>(), vec![0x0FFE, 0x1000, 0x1000]);
+		assert_eq!(xrefs.to(0x2000).len(), 3);
+		assert_eq!(XrefKind::Pointer.access(), SectionFlags::Execute);
+		assert_eq!(XrefKind::Write.access(), SectionFlags::Write);
+	}
+}